@@ -0,0 +1,175 @@
+use crate::hash::HashAlgorithm;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+const INDEX_FILE: &str = "index.json";
+const OBJECTS_DIR: &str = "objects";
+
+// Validators the server sent with a file and the hash of its content
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct CacheEntry {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub etag: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_modified: Option<String>,
+    // Extension of the hash algorithm, as in sha256
+    pub algorithm: String,
+    pub hash: String,
+    pub size: u64,
+}
+
+// Downloaded files of one server below <cache dir>/<host>. index.json maps file names to their
+// entries, the content is stored once per hash in objects/, so a file that was renamed on the
+// server is not stored twice.
+pub struct DownloadCache {
+    dir: PathBuf,
+    algorithm: HashAlgorithm,
+    index: Mutex<BTreeMap<String, CacheEntry>>,
+    // Numbers the temporary files of downloads in progress
+    next_temp: AtomicU64,
+}
+
+impl DownloadCache {
+    pub fn open(cache_dir: &Path, server_url: &str, algorithm: HashAlgorithm) -> io::Result<Self> {
+        let url = reqwest::Url::parse(server_url)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let host = url.host_str().unwrap_or("localhost");
+        // Brackets and colons of IPv6 literals are not valid in directory names everywhere
+        let mut host_dir = host.replace(['[', ']', ':'], "_");
+        if let Some(port) = url.port() {
+            host_dir = format!("{}_{}", host_dir, port);
+        }
+        let dir = cache_dir.join(host_dir);
+        std::fs::create_dir_all(dir.join(OBJECTS_DIR))?;
+
+        // A damaged index only costs a download of every file again
+        let index = match File::open(dir.join(INDEX_FILE)) {
+            Ok(file) => serde_json::from_reader(BufReader::new(file)).unwrap_or_else(|e| {
+                tracing::warn!(
+                    "Ignoring the damaged cache index in {}: {}",
+                    dir.display(),
+                    e
+                );
+                BTreeMap::new()
+            }),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e),
+        };
+        Ok(Self {
+            dir,
+            algorithm,
+            index: Mutex::new(index),
+            next_temp: AtomicU64::new(0),
+        })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    // Entry of a file whose content is still in the cache and was hashed with the current algorithm
+    pub fn lookup(&self, name: &str) -> Option<CacheEntry> {
+        let entry = self.index.lock().unwrap().get(name).cloned()?;
+        let size = self.object_path(&entry).metadata().ok()?.len();
+        (entry.algorithm == self.extension() && size == entry.size).then_some(entry)
+    }
+
+    pub fn object_path(&self, entry: &CacheEntry) -> PathBuf {
+        self.dir
+            .join(OBJECTS_DIR)
+            .join(format!("{}-{}", entry.algorithm, entry.hash))
+    }
+
+    // Temporary file a download is written to until its hash, and so its object name, is known
+    pub fn temp_file(&self) -> io::Result<(BufWriter<File>, PathBuf)> {
+        let path = self.dir.join(OBJECTS_DIR).join(format!(
+            ".partial-{}-{}",
+            std::process::id(),
+            self.next_temp.fetch_add(1, Ordering::Relaxed)
+        ));
+        Ok((BufWriter::new(File::create(&path)?), path))
+    }
+
+    // Moves a completed temporary file into the cache under its hash and records it for `name`
+    pub fn store(
+        &self,
+        name: &str,
+        temp: &Path,
+        etag: Option<String>,
+        last_modified: Option<String>,
+        hash: &str,
+        size: u64,
+    ) -> io::Result<()> {
+        let entry = CacheEntry {
+            etag,
+            last_modified,
+            algorithm: self.extension().to_string(),
+            hash: hash.to_string(),
+            size,
+        };
+        let object = self.object_path(&entry);
+        if object.exists() {
+            std::fs::remove_file(temp)?;
+        } else {
+            std::fs::rename(temp, &object)?;
+        }
+        let mut index = self.index.lock().unwrap();
+        index.insert(name.to_string(), entry);
+        self.write_index(&index)
+    }
+
+    // Written to a temporary file first, so an interrupted run never leaves a truncated index
+    fn write_index(&self, index: &BTreeMap<String, CacheEntry>) -> io::Result<()> {
+        let temp = self.dir.join(format!(".{}.tmp", INDEX_FILE));
+        let mut writer = BufWriter::new(File::create(&temp)?);
+        serde_json::to_writer_pretty(&mut writer, index)?;
+        writeln!(writer)?;
+        writer.flush()?;
+        drop(writer);
+        std::fs::rename(temp, self.dir.join(INDEX_FILE))
+    }
+
+    fn extension(&self) -> &'static str {
+        self.algorithm.extension().unwrap_or("none")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempPath;
+
+    #[test]
+    fn download_cache_stores_content_once() {
+        let dir = TempPath::new("cache");
+        let server = "http://localhost:8080";
+        let cache = DownloadCache::open(&dir, server, HashAlgorithm::Sha256).unwrap();
+        assert!(cache.lookup("a.bin").is_none());
+        for (name, etag) in [("a.bin", "\"1\""), ("renamed.bin", "\"2\"")] {
+            let (mut writer, temp) = cache.temp_file().unwrap();
+            writer.write_all(b"data").unwrap();
+            drop(writer);
+            let etag = Some(etag.to_string());
+            cache.store(name, &temp, etag, None, "abcd", 4).unwrap();
+        }
+        assert_eq!(
+            std::fs::read_dir(dir.join("localhost_8080").join("objects"))
+                .unwrap()
+                .count(),
+            1
+        );
+
+        // The index survives, entries of another hash algorithm are misses
+        let cache = DownloadCache::open(&dir, server, HashAlgorithm::Sha256).unwrap();
+        let entry = cache.lookup("renamed.bin").unwrap();
+        assert_eq!(entry.etag.as_deref(), Some("\"2\""));
+        assert_eq!(std::fs::read(cache.object_path(&entry)).unwrap(), b"data");
+        let cache = DownloadCache::open(&dir, server, HashAlgorithm::Blake3).unwrap();
+        assert!(cache.lookup("a.bin").is_none());
+    }
+}
@@ -0,0 +1,1057 @@
+use crate::writeout::WriteOut;
+use clap::builder::ArgPredicate;
+use clap::{Arg, ArgAction, Command};
+
+use reqwest::header::{HeaderName, HeaderValue};
+
+use crate::http::DEFAULT_USER_AGENT;
+
+// Parses a "Name: Value" request header
+fn parse_header(raw: &str) -> Result<(HeaderName, HeaderValue), String> {
+    let (name, value) = raw
+        .split_once(':')
+        .ok_or_else(|| "expected \"Name: Value\"".to_string())?;
+    let name = HeaderName::from_bytes(name.trim().as_bytes())
+        .map_err(|e| format!("invalid header name {:?}: {}", name.trim(), e))?;
+    let value = HeaderValue::from_str(value.trim())
+        .map_err(|e| format!("invalid value for header {}: {}", name, e))?;
+    Ok((name, value))
+}
+
+// Parses a size in bytes with an optional binary suffix, e.g. "4096", "64K", "10MB" or "2GiB"
+pub fn parse_size(raw: &str) -> Result<usize, String> {
+    let raw = raw.trim();
+    let digits = raw.find(|c: char| !c.is_ascii_digit()).unwrap_or(raw.len());
+    let (number, suffix) = raw.split_at(digits);
+    let number: usize = number
+        .parse()
+        .map_err(|_| format!("invalid size {:?}", raw))?;
+    let multiplier: usize = match suffix.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1024,
+        "M" | "MB" | "MIB" => 1024 * 1024,
+        "G" | "GB" | "GIB" => 1024 * 1024 * 1024,
+        _ => return Err(format!("unknown size suffix {:?}", suffix)),
+    };
+    number
+        .checked_mul(multiplier)
+        .ok_or_else(|| format!("size {:?} is too large", raw))
+}
+
+// Parses a transfer rate in bytes per second with an optional SI suffix, e.g. "500K" or "10M" for
+// 10 MB/s. Binary suffixes like "MiB" are accepted too, 0 means unlimited.
+pub fn parse_rate(raw: &str) -> Result<u64, String> {
+    let raw = raw.trim();
+    let digits = raw.find(|c: char| !c.is_ascii_digit()).unwrap_or(raw.len());
+    let (number, suffix) = raw.split_at(digits);
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("invalid rate {:?}", raw))?;
+    let suffix = suffix.trim().trim_end_matches("/s").to_ascii_uppercase();
+    let multiplier: u64 = match suffix.as_str() {
+        "" | "B" => 1,
+        "K" | "KB" => 1000,
+        "M" | "MB" => 1000 * 1000,
+        "G" | "GB" => 1000 * 1000 * 1000,
+        "KIB" => 1024,
+        "MIB" => 1024 * 1024,
+        "GIB" => 1024 * 1024 * 1024,
+        _ => return Err(format!("unknown rate suffix {:?}", suffix)),
+    };
+    number
+        .checked_mul(multiplier)
+        .ok_or_else(|| format!("rate {:?} is too large", raw))
+}
+
+// Like `parse_size`, for sizes that must not be zero
+fn parse_size_nonzero(raw: &str) -> Result<usize, String> {
+    match parse_size(raw)? {
+        0 => Err("size must be greater than zero".to_string()),
+        size => Ok(size),
+    }
+}
+
+// Rejects MIME types that are malformed or cannot be sent in a header, e.g. "application/json"
+fn parse_mime(raw: &str) -> Result<String, String> {
+    let mime: mime::Mime = raw
+        .parse()
+        .map_err(|e| format!("invalid MIME type {:?}: {}", raw, e))?;
+    HeaderValue::from_str(mime.as_ref())
+        .map(|_| raw.to_string())
+        .map_err(|e| format!("invalid MIME type {:?}: {}", raw, e))
+}
+
+// Parses a "name=value" cookie, which must be sendable in a Cookie header
+fn parse_cookie(raw: &str) -> Result<(String, String), String> {
+    let (name, value) = raw
+        .split_once('=')
+        .ok_or_else(|| "expected \"name=value\"".to_string())?;
+    let name = name.trim();
+    if name.is_empty() || name.contains([';', ' ']) || value.contains(';') {
+        return Err(format!("invalid cookie {:?}", raw));
+    }
+    HeaderValue::from_str(raw)
+        .map(|_| (name.to_string(), value.trim().to_string()))
+        .map_err(|e| format!("invalid cookie {:?}: {}", raw, e))
+}
+
+// Checks that the server is an http(s) URL and removes trailing slashes, so endpoint paths can be
+// appended with a single slash
+pub fn parse_server_url(raw: &str) -> Result<String, String> {
+    if !raw.contains("://") {
+        return Err(format!(
+            "{:?} has no scheme, did you mean http://{}?",
+            raw, raw
+        ));
+    }
+    let url = reqwest::Url::parse(raw).map_err(|e| format!("invalid URL {:?}: {}", raw, e))?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(format!(
+            "unsupported scheme {:?} in {:?}, use http:// or https://",
+            url.scheme(),
+            raw
+        ));
+    }
+    if url.host_str().is_none_or(str::is_empty) {
+        return Err(format!("{:?} has no host", raw));
+    }
+    if url.query().is_some() || url.fragment().is_some() {
+        return Err(format!("{:?} must not have a query or fragment", raw));
+    }
+    Ok(url.as_str().trim_end_matches('/').to_string())
+}
+
+// Accepts a hex digest of any length, normalized to lowercase like the computed hashes
+fn parse_hex_hash(raw: &str) -> Result<String, String> {
+    if raw.is_empty() || !raw.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err("expected a hexadecimal hash".to_string());
+    }
+    Ok(raw.to_ascii_lowercase())
+}
+
+// Rejects User-Agent strings that are not valid header values
+fn parse_user_agent(raw: &str) -> Result<String, String> {
+    HeaderValue::from_str(raw)
+        .map(|_| raw.to_string())
+        .map_err(|e| format!("invalid User-Agent {:?}: {}", raw, e))
+}
+
+// Rejects strftime formats that chrono cannot render
+fn parse_time_format(raw: &str) -> Result<String, String> {
+    let invalid = chrono::format::StrftimeItems::new(raw)
+        .any(|item| matches!(item, chrono::format::Item::Error));
+    if invalid {
+        Err(format!("invalid strftime format {:?}", raw))
+    } else {
+        Ok(raw.to_string())
+    }
+}
+
+fn file_arg(help: &'static str) -> Arg {
+    Arg::new("file")
+        .value_name("FILE")
+        .help(help)
+        .required(true)
+}
+
+// Arguments shared by the upload and roundtrip commands
+fn upload_args() -> [Arg; 12] {
+    [
+        Arg::new("check")
+            .long("check")
+            .value_name("CHECKSUM_FILE")
+            .help("Verifies local files against a sha256sum-style checksum file, aborts the upload on mismatch"),
+        Arg::new("upload-if-absent")
+            .long("upload-if-absent")
+            .help("Skips the upload if a HEAD request finds the file on the server already")
+            .action(ArgAction::SetTrue),
+        Arg::new("upload-if-changed")
+            .long("upload-if-changed")
+            .help("Skips the upload if the server lists the file with the SHA256 of the local file")
+            .action(ArgAction::SetTrue)
+            .conflicts_with("upload-if-absent"),
+        Arg::new("list-endpoint")
+            .long("list-endpoint")
+            .value_name("PATH")
+            .help("Sets the listing endpoint --upload-if-changed looks up server hashes in (default: files)")
+            .requires("upload-if-changed"),
+        Arg::new("field-name")
+            .long("field-name")
+            .visible_alias("form-field")
+            .value_name("NAME")
+            .help("Sets the multipart field name of the file part")
+            .default_value("file"),
+        Arg::new("remote-name")
+            .long("remote-name")
+            .value_name("NAME")
+            .help("Sets the filename sent in the Content-Disposition header instead of the local file name"),
+        Arg::new("upload-chunk-size")
+            .long("upload-chunk-size")
+            .value_name("SIZE")
+            .help("Uploads the file in parts of this size (e.g. 64MB) to the chunked upload endpoint, retrying each part on its own")
+            .value_parser(parse_size_nonzero),
+        Arg::new("method")
+            .long("method")
+            .value_name("METHOD")
+            .help("Sends a multipart form with POST, or the raw file bytes with PUT to <server>/<name>")
+            .value_parser(["post", "put"])
+            .ignore_case(true)
+            .default_value("post")
+            .conflicts_with("upload-chunk-size"),
+        Arg::new("upload-path")
+            .long("upload-path")
+            .value_name("TEMPLATE")
+            .help("Sets the upload endpoint path, {name} or {file} is replaced by the remote file name (default: /upload, or /{name} with --method put)")
+            .conflicts_with("upload-chunk-size"),
+        Arg::new("content-type")
+            .long("content-type")
+            .value_name("MIME")
+            .help("Sets the Content-Type of the file part, or of the body with --method put, instead of guessing it from the extension")
+            .value_parser(parse_mime),
+        Arg::new("compress")
+            .long("compress")
+            .value_name("ALGORITHM")
+            .help("Compresses the upload on the fly and sends it with Content-Encoding, the printed SHA256 is of the uncompressed file")
+            .value_parser(["gzip"])
+            .conflicts_with("upload-chunk-size"),
+        Arg::new("compress-upload")
+            .long("compress-upload")
+            .help("Same as --compress gzip")
+            .action(ArgAction::SetTrue)
+            .conflicts_with_all(["compress", "upload-chunk-size"]),
+    ]
+}
+
+// Arguments shared by the download and roundtrip commands
+fn download_args() -> [Arg; 18] {
+    [
+        Arg::new("verify")
+            .long("verify")
+            .value_name("LOCAL_PATH")
+            .help("Compares the downloaded file against the hash of a local file, of the --hash-algorithm (SHA256 by default), exits non-zero on mismatch"),
+        Arg::new("save-hash")
+            .long("save-hash")
+            .help("Writes the --hash-algorithm hash of the downloaded file to a sidecar next to it, e.g. data.bin.sha256 or data.bin.blake3")
+            .action(ArgAction::SetTrue),
+        Arg::new("expected-hash")
+            .long("expected-hash")
+            .value_name("HEX")
+            .help("Compares the hash of the downloaded file against this value, exits non-zero on mismatch")
+            .value_parser(parse_hex_hash)
+            .conflicts_with("verify"),
+        Arg::new("output")
+            .long("output")
+            .short('o')
+            .value_name("PATH")
+            .help(
+                "Saves the downloaded file, a directory path receives the server-relative filename, - writes to stdout",
+            )
+            .conflicts_with_all(["output-dir", "output-template"])
+            .default_value_if("stdout", ArgPredicate::IsPresent, "-"),
+        Arg::new("output-dir")
+            .long("output-dir")
+            .value_name("DIR")
+            .help("Saves the downloaded file below this directory, which is created if missing"),
+        Arg::new("output-template")
+            .long("output-template")
+            .value_name("TEMPLATE")
+            .help("Saves every iteration to its own file, {n} is replaced by the iteration and {timestamp} by the current time"),
+        Arg::new("overwrite")
+            .long("overwrite")
+            .help("Replaces existing local files instead of refusing to download")
+            .action(ArgAction::SetTrue),
+        Arg::new("continue")
+            .long("continue")
+            .help("Continues a partially downloaded output file with a Range request")
+            .action(ArgAction::SetTrue)
+            .conflicts_with("overwrite"),
+        Arg::new("parallel-chunks")
+            .long("parallel-chunks")
+            .value_name("NUMBER")
+            .help("Downloads the file as this many byte ranges in parallel, requires an output file")
+            .value_parser(clap::builder::RangedU64ValueParser::<usize>::new().range(1..))
+            .default_value("1")
+            .conflicts_with("continue"),
+        Arg::new("cache-dir")
+            .long("cache-dir")
+            .value_name("DIR")
+            .help("Caches downloaded files and revalidates them with If-None-Match or If-Modified-Since, a 304 is served from the cache")
+            .conflicts_with_all(["continue", "parallel-chunks"]),
+        Arg::new("min-speed")
+            .long("min-speed")
+            .value_name("BYTES_PER_SEC")
+            .help("Aborts (and retries) a download whose average speed over 5 seconds stays below this, accepts SI K/M/G suffixes")
+            .value_parser(parse_rate),
+        Arg::new("min-speed-timeout")
+            .long("min-speed-timeout")
+            .value_name("SECONDS")
+            .help("Sets how long the speed may stay below --min-speed before the download is aborted")
+            .value_parser(clap::value_parser!(u64))
+            .default_value("30"),
+        Arg::new("accept-encoding")
+            .long("accept-encoding")
+            .value_name("ENCODING")
+            .help("Requests a content encoding (none omits the header), the response is decoded and both decoded and on-the-wire sizes are reported")
+            .value_parser(["gzip", "br", "identity", "none"])
+            .conflicts_with_all(["continue", "parallel-chunks"]),
+        Arg::new("decompress-download")
+            .long("decompress-download")
+            .help("Accepts gzip, deflate and br encoded responses and decodes them, like curl --compressed")
+            .action(ArgAction::SetTrue)
+            .conflicts_with_all(["accept-encoding", "continue", "parallel-chunks"]),
+        Arg::new("chunked")
+            .long("chunked")
+            .short('c')
+            .help("Enables chunked download")
+            .action(ArgAction::SetTrue),
+        Arg::new("assert-exists")
+            .long("assert-exists")
+            .help("Checks that the file exists on the server (HEAD request) before downloading")
+            .action(ArgAction::SetTrue),
+        Arg::new("min-size")
+            .long("min-size")
+            .value_name("BYTES")
+            .help("Aborts the download if the remote file is smaller than this")
+            .value_parser(clap::value_parser!(u64)),
+        Arg::new("max-size")
+            .long("max-size")
+            .value_name("BYTES")
+            .help("Aborts the download if the remote file is larger than this")
+            .value_parser(clap::value_parser!(u64)),
+    ]
+}
+
+pub fn build_cli() -> Command {
+    Command::new("File Server Client")
+        .version("1.0")
+        .author("Vadim Smirnov <vadim@ntkernel.com>")
+        .about("Handles file operations with a server")
+        .arg(
+            Arg::new("config")
+                .long("config")
+                .value_name("PATH")
+                .help("Reads defaults from this config file instead of ./simple-file-client.toml or ~/.config/simple-file-client.toml")
+                .global(true),
+        )
+        .arg(
+            Arg::new("profile")
+                .long("profile")
+                .value_name("NAME")
+                .help("Applies the [profiles.NAME] settings of the config file")
+                .global(true),
+        )
+        .arg(
+            Arg::new("server")
+                .long("server")
+                .short('s')
+                .value_name("URL")
+                .help("Sets the server URL")
+                .value_parser(parse_server_url)
+                .global(true),
+        )
+        .arg(
+            Arg::new("timeout")
+                .long("request-timeout")
+                .visible_alias("timeout")
+                .short('t')
+                .value_name("SECONDS")
+                .help("Limits every whole request, from connecting until the body is transferred, unlike --connect-timeout")
+                .value_parser(clap::value_parser!(u64))
+                .default_value("30")
+                .global(true),
+        )
+        .arg(
+            Arg::new("connect-timeout")
+                .long("connect-timeout")
+                .value_name("SECONDS")
+                .help("Limits establishing a connection only, so unreachable servers fail fast without shortening transfers")
+                .value_parser(clap::value_parser!(u64))
+                .default_value("10")
+                .global(true),
+        )
+        .arg(
+            Arg::new("iterations")
+                .long("iterations")
+                .short('i')
+                .value_name("NUMBER")
+                .help("Specifies the number of iterations for upload/download")
+                .default_value("1")
+                .global(true),
+        )
+        .arg(
+            Arg::new("rate")
+                .long("rate")
+                .value_name("PER_SEC")
+                .help("Starts iterations (or, with --recursive, uploads) at this steady rate per second instead of back to back")
+                .value_parser(clap::value_parser!(u64).range(1..))
+                .global(true),
+        )
+        .arg(
+            Arg::new("retries")
+                .long("retries")
+                .value_name("NUMBER")
+                .help("Retries a failed upload or download this many times")
+                .value_parser(clap::value_parser!(u32))
+                .default_value("0")
+                .global(true),
+        )
+        .arg(
+            Arg::new("max-redirects")
+                .long("max-redirects")
+                .value_name("NUMBER")
+                .help("Follows at most this many redirects (default: 10), 0 fails on a redirect and reports its Location")
+                .value_parser(clap::value_parser!(usize))
+                .global(true),
+        )
+        .arg(
+            Arg::new("no-follow")
+                .long("no-follow")
+                .help("Does not follow redirects, same as --max-redirects 0")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("max-redirects")
+                .global(true),
+        )
+        .arg(
+            Arg::new("user")
+                .long("user")
+                .short('u')
+                .value_name("USER:PASSWORD")
+                .help("Sends HTTP basic authentication credentials")
+                .global(true),
+        )
+        .arg(
+            Arg::new("token")
+                .long("token")
+                .value_name("TOKEN")
+                .help("Sends a bearer token in the Authorization header")
+                .conflicts_with("user")
+                .global(true),
+        )
+        .arg(
+            Arg::new("verify-certs")
+                .long("verify-certs")
+                .help("Verifies server certificates, by default any certificate is accepted")
+                .action(ArgAction::SetTrue)
+                .global(true),
+        )
+        .arg(
+            Arg::new("cacert")
+                .long("cacert")
+                .value_name("FILE")
+                .help("Trusts the root certificate in this PEM file, implies --verify-certs")
+                .global(true),
+        )
+        .arg(
+            Arg::new("insecure")
+                .long("insecure")
+                .help("Accepts any server certificate, overriding the config file")
+                .action(ArgAction::SetTrue)
+                .conflicts_with_all(["verify-certs", "cacert"])
+                .global(true),
+        )
+        .arg(
+            Arg::new("proxy")
+                .long("proxy")
+                .value_name("URL")
+                .help("Routes all requests through the specified proxy (credentials may be embedded in the URL)")
+                .global(true),
+        )
+        .arg(
+            Arg::new("ipv4")
+                .long("ipv4")
+                .short('4')
+                .help("Connects over IPv4 only")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("ipv6")
+                .global(true),
+        )
+        .arg(
+            Arg::new("ipv6")
+                .long("ipv6")
+                .short('6')
+                .help("Connects over IPv6 only")
+                .action(ArgAction::SetTrue)
+                .global(true),
+        )
+        .arg(
+            Arg::new("http1")
+                .long("http1")
+                .help("Only uses HTTP/1.1, even if the server offers HTTP/2")
+                .action(ArgAction::SetTrue)
+                .conflicts_with("http2")
+                .global(true),
+        )
+        .arg(
+            Arg::new("http2")
+                .long("http2")
+                .help("Uses HTTP/2 with prior knowledge, the server must support it")
+                .action(ArgAction::SetTrue)
+                .global(true),
+        )
+        .arg(
+            Arg::new("persist-cookies")
+                .long("persist-cookies")
+                .visible_alias("cookies")
+                .help("Keeps cookies set by the server and sends them with later requests, e.g. a session cookie")
+                .action(ArgAction::SetTrue)
+                .global(true),
+        )
+        .arg(
+            Arg::new("cookie")
+                .long("cookie")
+                .value_name("NAME=VALUE")
+                .help("Sends a cookie with every request, may be specified multiple times")
+                .value_parser(parse_cookie)
+                .action(ArgAction::Append)
+                .global(true),
+        )
+        .arg(
+            Arg::new("cookie-jar")
+                .long("cookie-jar")
+                .value_name("FILE")
+                .help("Loads cookies from a Netscape cookie file and saves the cookies the server sets to it")
+                .global(true),
+        )
+        .arg(
+            Arg::new("no-keepalive")
+                .long("no-keepalive")
+                .help("Opens a new connection for every request to measure the full connection setup cost")
+                .action(ArgAction::SetTrue)
+                .global(true),
+        )
+        .arg(
+            Arg::new("print-ip")
+                .long("print-ip")
+                .help("Logs the IP address and port of the server a response came from")
+                .action(ArgAction::SetTrue)
+                .global(true),
+        )
+        .arg(
+            Arg::new("user-agent")
+                .long("user-agent")
+                .value_name("STRING")
+                .help("Sets the User-Agent header sent with every request")
+                .value_parser(parse_user_agent)
+                .default_value(DEFAULT_USER_AGENT)
+                .global(true),
+        )
+        .arg(
+            Arg::new("verbose")
+                .long("verbose")
+                .short('v')
+                .help("Increases log verbosity (-v debug, -vv trace)")
+                .action(ArgAction::Count)
+                .global(true),
+        )
+        .arg(
+            Arg::new("quiet")
+                .long("quiet")
+                .short('q')
+                .help("Prints only the bare result (a SHA256 or OK/FAIL), list --json and errors, -qq suppresses all but errors")
+                .action(ArgAction::Count)
+                .conflicts_with("verbose")
+                .global(true),
+        )
+        .arg(
+            Arg::new("no-progress")
+                .long("no-progress")
+                .help("Hides the progress bars shown for transfers and file generation on terminals")
+                .action(ArgAction::SetTrue)
+                .global(true),
+        )
+        .arg(
+            Arg::new("log-level")
+                .long("log-level")
+                .value_name("LEVEL")
+                .help("Sets the log level, overriding -v/--quiet and RUST_LOG")
+                .value_parser(["trace", "debug", "info", "warn", "error"])
+                .conflicts_with_all(["verbose", "quiet"])
+                .global(true),
+        )
+        .arg(
+            Arg::new("log-file")
+                .long("log-file")
+                .value_name("FILE")
+                .help("Appends log output to a file instead of the terminal, errors are still printed")
+                .global(true),
+        )
+        .arg(
+            Arg::new("color")
+                .long("color")
+                .value_name("WHEN")
+                .help("Colours success, warning and error output")
+                .value_parser(["auto", "always", "never"])
+                .default_value("auto")
+                .global(true),
+        )
+        .arg(
+            Arg::new("ok-status")
+                .long("ok-status")
+                .value_name("CODES")
+                .help("Also accepts these comma-separated status codes as success, besides 2xx")
+                .value_parser(clap::value_parser!(u16).range(100..=599))
+                .value_delimiter(',')
+                .action(ArgAction::Append)
+                .global(true),
+        )
+        .arg(
+            Arg::new("expect-status")
+                .long("expect-status")
+                .value_name("CODES")
+                .help("Succeeds only if uploads, downloads and deletes return one of these comma-separated status codes, e.g. 404")
+                .value_parser(clap::value_parser!(u16).range(100..=599))
+                .value_delimiter(',')
+                .action(ArgAction::Append)
+                .conflicts_with("ok-status")
+                .global(true),
+        )
+        .arg(
+            Arg::new("hash-algorithm")
+                .long("hash-algorithm")
+                .value_name("ALGORITHM")
+                .help("Sets the algorithm of the printed and verified file hashes, none skips hashing")
+                .value_parser(["sha256", "sha512", "blake3", "none"])
+                .default_value("sha256")
+                .global(true),
+        )
+        .arg(
+            Arg::new("slow-threshold")
+                .long("slow-threshold")
+                .value_name("SECONDS")
+                .help("Highlights transfers taking longer than this")
+                .value_parser(clap::value_parser!(f64))
+                .default_value("30")
+                .global(true),
+        )
+        .arg(
+            Arg::new("utc")
+                .long("utc")
+                .help("Prints log timestamps in UTC instead of local time")
+                .action(ArgAction::SetTrue)
+                .global(true),
+        )
+        .arg(
+            Arg::new("time-format")
+                .long("time-format")
+                .value_name("FORMAT")
+                .help("Sets the strftime format of log timestamps")
+                .value_parser(parse_time_format)
+                .global(true),
+        )
+        .arg(
+            Arg::new("audit-log")
+                .long("audit-log")
+                .value_name("FILE")
+                .help("Appends one JSON line per HTTP request to a file")
+                .global(true),
+        )
+        .arg(
+            Arg::new("dump-headers")
+                .long("dump-headers")
+                .help("Prints the method, URL and headers of every request and the status and headers of every response to stderr")
+                .action(ArgAction::SetTrue)
+                .global(true),
+        )
+        .arg(
+            Arg::new("dump-body")
+                .long("dump-body")
+                .help("Also logs response bodies in verbose mode")
+                .action(ArgAction::SetTrue)
+                .global(true),
+        )
+        .arg(
+            Arg::new("csv")
+                .long("csv")
+                .value_name("PATH")
+                .help("Writes one CSV row per upload and download with its timing, size, status and SHA256")
+                .global(true),
+        )
+        .arg(
+            Arg::new("async")
+                .long("async")
+                .help("Runs the upload or download iterations as concurrent async transfers, for load tests")
+                .action(ArgAction::SetTrue)
+                .global(true),
+        )
+        .arg(
+            Arg::new("concurrency")
+                .long("concurrency")
+                .value_name("NUMBER")
+                .help("Caps the transfers in flight with --async")
+                .value_parser(clap::builder::RangedU64ValueParser::<usize>::new().range(1..))
+                .default_value("100")
+                .global(true),
+        )
+        .arg(
+            Arg::new("write-out")
+                .long("write-out")
+                .short('w')
+                .value_name("FORMAT")
+                .help("Prints FORMAT after every upload and download, e.g. \"%{http_status} %{size} %{time_total}\\n\"")
+                .value_parser(WriteOut::parse)
+                .global(true),
+        )
+        .arg(
+            Arg::new("timing")
+                .long("timing")
+                .help("Logs DNS, connect, TLS, time to first byte and transfer times of every upload and download")
+                .action(ArgAction::SetTrue)
+                .global(true),
+        )
+        .arg(
+            Arg::new("limit-rate")
+                .long("limit-rate")
+                .value_name("BYTES_PER_SEC")
+                .help("Caps upload and download speed, accepts SI K/M/G suffixes, 0 is unlimited")
+                .value_parser(parse_rate)
+                .global(true),
+        )
+        .arg(
+            Arg::new("upload-rate")
+                .long("upload-rate")
+                .value_name("BYTES_PER_SEC")
+                .help("Caps upload speed only, overrides --limit-rate for uploads, accepts SI K/M/G suffixes, 0 is unlimited")
+                .value_parser(parse_rate)
+                .global(true),
+        )
+        .arg(
+            Arg::new("download-rate")
+                .long("download-rate")
+                .value_name("BYTES_PER_SEC")
+                .help("Caps download speed only, overrides --limit-rate for downloads, accepts SI K/M/G suffixes, 0 is unlimited")
+                .value_parser(parse_rate)
+                .global(true),
+        )
+        .arg(
+            Arg::new("download-path")
+                .long("download-path")
+                .value_name("TEMPLATE")
+                .help("Sets the download endpoint path, {name} or {file} is replaced by the file name")
+                .default_value("/download/{name}")
+                .global(true),
+        )
+        .arg(
+            Arg::new("download-chunked-path")
+                .long("download-chunked-path")
+                .visible_alias("chunked-path")
+                .value_name("TEMPLATE")
+                .help("Sets the endpoint path of --chunked downloads, {name} or {file} is replaced by the file name")
+                .default_value("/download-chunked/{name}")
+                .global(true),
+        )
+        .arg(
+            Arg::new("encode-slash")
+                .long("encode-slash")
+                .help("Percent-encodes '/' in file names as %2F instead of sending it as a path separator")
+                .action(ArgAction::SetTrue)
+                .global(true),
+        )
+        .arg(
+            Arg::new("delete-path")
+                .long("delete-path")
+                .value_name("TEMPLATE")
+                .help("Sets the delete endpoint path, {name} or {file} is replaced by the file name")
+                .default_value("/{name}")
+                .global(true),
+        )
+        .arg(
+            Arg::new("head-path")
+                .long("head-path")
+                .value_name("TEMPLATE")
+                .help("Sets the path of the HEAD requests that check whether a file exists and its size, {name} or {file} is replaced by the file name")
+                .default_value("/{name}")
+                .global(true),
+        )
+        .arg(
+            Arg::new("header")
+                .long("header")
+                .short('H')
+                .value_name("NAME: VALUE")
+                .help("Adds a custom header to every request, may be specified multiple times")
+                .value_parser(parse_header)
+                .action(ArgAction::Append)
+                .global(true),
+        )
+        .subcommand(
+            Command::new("generate")
+                .about("Generates a file of specified size")
+                .arg(file_arg(
+                    "The file to generate, {n} or zero-padded {n:03} is replaced by the file number",
+                ))
+                .arg(
+                    Arg::new("size")
+                        .long("size")
+                        .value_name("SIZE")
+                        .help("Sets the file size for generation, accepts K/M/G suffixes")
+                        .value_parser(parse_size)
+                        .default_value("1024"),
+                )
+                .arg(
+                    Arg::new("force")
+                        .long("force")
+                        .short('f')
+                        .help("Regenerates the file even if it already exists with the requested size")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("count")
+                        .long("count")
+                        .value_name("NUMBER")
+                        .help("Generates a batch of files named from the {n} template")
+                        .value_parser(clap::value_parser!(usize))
+                        .default_value("1"),
+                )
+                .arg(
+                    Arg::new("gen-block-size")
+                        .long("gen-block-size")
+                        .value_name("SIZE")
+                        .help("Sets how much data is generated, hashed and written at a time (default: 1M), accepts K/M/G suffixes")
+                        .value_parser(parse_size_nonzero),
+                )
+                .arg(
+                    Arg::new("gen-threads")
+                        .long("gen-threads")
+                        .value_name("NUMBER")
+                        .help("Caps the worker threads generating a batch, by default and at most one per CPU core")
+                        .value_parser(clap::builder::RangedU64ValueParser::<usize>::new().range(1..)),
+                )
+                .arg(
+                    Arg::new("parallel-hash")
+                        .long("parallel-hash")
+                        .value_name("NUMBER")
+                        .help("Hashes each generated file on this many threads, with --hash-algorithm blake3")
+                        .value_parser(clap::builder::RangedU64ValueParser::<usize>::new().range(1..)),
+                )
+                .arg(
+                    Arg::new("output-dir")
+                        .long("output-dir")
+                        .value_name("DIR")
+                        .help("Generates the files in this directory, which is created if needed"),
+                )
+                .arg(
+                    Arg::new("manifest")
+                        .long("manifest")
+                        .value_name("FILE")
+                        .help("Writes the sha256sum-style manifest of the generated files"),
+                )
+                .arg(
+                    Arg::new("seed")
+                        .long("seed")
+                        .value_name("SEED")
+                        .help("Sets the RNG seed for reproducible file generation")
+                        .value_parser(clap::value_parser!(u64)),
+                )
+                .arg(
+                    Arg::new("content")
+                        .long("content")
+                        .value_name("KIND")
+                        .help("Sets the kind of generated data: alphanumeric text or full-byte-range binary")
+                        .value_parser(["text", "binary"])
+                        .default_value("text"),
+                )
+                .arg(
+                    Arg::new("compressibility")
+                        .long("compressibility")
+                        .value_name("PERCENT")
+                        .help("Sets the percentage (0-100) of each generated block that is zero-filled")
+                        .value_parser(clap::value_parser!(u8).range(0..=100))
+                        .default_value("0"),
+                ),
+        )
+        .subcommand(
+            Command::new("upload")
+                .about("Uploads the specified file")
+                .arg(
+                    file_arg("The file to upload ('-' for standard input), several files are sent in a single multipart request")
+                        .num_args(1..)
+                        .required(false)
+                        .required_unless_present("stdin")
+                        .default_value_if("stdin", ArgPredicate::IsPresent, "-"),
+                )
+                .args(upload_args())
+                .arg(
+                    Arg::new("stdin")
+                        .long("stdin")
+                        .help("Uploads standard input, the same as the file '-'")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with("file")
+                        .requires("remote-name"),
+                )
+                .arg(
+                    Arg::new("recursive")
+                        .long("recursive")
+                        .short('r')
+                        .help("Uploads every file below the given directories under its relative path")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with_all(["remote-name", "upload-chunk-size", "compress", "compress-upload"]),
+                )
+                .arg(
+                    Arg::new("manifest")
+                        .long("manifest")
+                        .value_name("PATH")
+                        .help("Writes a JSON manifest of the uploaded files with their names, hashes and sizes"),
+                )
+                .arg(
+                    Arg::new("follow-symlinks")
+                        .long("follow-symlinks")
+                        .help("Follows symlinks when walking directories instead of skipping them")
+                        .action(ArgAction::SetTrue)
+                        .requires("recursive"),
+                ),
+        )
+        .subcommand(
+            Command::new("download")
+                .about("Downloads the specified file")
+                .arg(file_arg("The file to download"))
+                .args(download_args())
+                .arg(
+                    Arg::new("manifest")
+                        .long("manifest")
+                        .value_name("PATH")
+                        .help("Verifies the download against its hash in a JSON manifest written by upload --manifest")
+                        .conflicts_with_all(["expected-hash", "verify"]),
+                )
+                .arg(
+                    Arg::new("stdout")
+                        .long("stdout")
+                        .help("Writes the downloaded file to stdout, the same as -o -")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with_all(["output", "output-dir", "output-template"]),
+                ),
+        )
+        .subcommand(
+            Command::new("roundtrip")
+                .about("Uploads the specified file and downloads it back")
+                .arg(file_arg("The file to upload and download"))
+                .args(upload_args())
+                .args(download_args()),
+        )
+        .subcommand(
+            Command::new("delete")
+                .about("Deletes the specified file from the server")
+                .arg(
+                    file_arg("The file to delete")
+                        .required(false)
+                        .required_unless_present("glob")
+                        .conflicts_with("glob"),
+                )
+                .arg(
+                    Arg::new("glob")
+                        .long("glob")
+                        .value_name("PATTERN")
+                        .help("Deletes every file of the server listing whose name matches the glob pattern, e.g. 'test_*.bin'"),
+                )
+                .arg(
+                    Arg::new("list-endpoint")
+                        .long("list-endpoint")
+                        .value_name("PATH")
+                        .help("Sets the listing endpoint path used with --glob")
+                        .default_value("files"),
+                )
+                .arg(
+                    Arg::new("yes")
+                        .long("yes")
+                        .short('y')
+                        .help("Deletes without asking for confirmation, required without a terminal")
+                        .action(ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("check")
+                .about("Verifies local files against a sha256sum-style checksum file")
+                .arg(
+                    Arg::new("checksum-file")
+                        .value_name("CHECKSUM_FILE")
+                        .help("The checksum file to verify")
+                        .required_unless_present("sidecar")
+                        .conflicts_with("sidecar"),
+                )
+                .arg(
+                    Arg::new("sidecar")
+                        .long("sidecar")
+                        .value_name("FILE")
+                        .help("Verifies each file against its sidecar of the --hash-algorithm, e.g. data.bin against data.bin.sha256 or data.bin.blake3")
+                        .num_args(1..),
+                ),
+        )
+        .subcommand(
+            Command::new("list")
+                .about("Lists the files stored on the server")
+                .arg(
+                    Arg::new("list-endpoint")
+                        .long("list-endpoint")
+                        .value_name("PATH")
+                        .help("Sets the listing endpoint path")
+                        .default_value("files"),
+                )
+                .arg(
+                    Arg::new("json")
+                        .long("json")
+                        .help("Prints the listing as JSON instead of a table")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("filter")
+                        .long("filter")
+                        .value_name("PATTERN")
+                        .help("Only shows files whose name matches the glob pattern"),
+                ),
+        )
+        .subcommand(
+            Command::new("exists")
+                .about("Checks whether a file exists on the server")
+                .arg(file_arg("The file to look up")),
+        )
+        .subcommand(
+            Command::new("completions")
+                .about("Prints a shell completion script to stdout")
+                .hide(true)
+                .arg(
+                    Arg::new("shell")
+                        .value_name("SHELL")
+                        .help("The shell to generate completions for")
+                        .value_parser(clap::value_parser!(clap_complete::Shell))
+                        .required(true),
+                ),
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rate_parsing() {
+        assert_eq!(parse_rate("0"), Ok(0));
+        assert_eq!(parse_rate("500K"), Ok(500_000));
+        assert_eq!(parse_rate("10M"), Ok(10_000_000));
+        assert_eq!(parse_rate("10MB/s"), Ok(10_000_000));
+        assert_eq!(parse_rate("1G"), Ok(1_000_000_000));
+        assert_eq!(parse_rate("2MiB"), Ok(2 * 1024 * 1024));
+        assert!(parse_rate("fast").is_err());
+        assert!(parse_rate("10X").is_err());
+    }
+
+    #[test]
+    fn server_url_validation() {
+        let parse = parse_server_url;
+        assert_eq!(
+            parse("http://localhost:8080/"),
+            Ok("http://localhost:8080".to_string())
+        );
+        assert_eq!(
+            parse("HTTPS://Files.Example.com"),
+            Ok("https://files.example.com".to_string())
+        );
+        assert_eq!(
+            parse("https://example.com/api//"),
+            Ok("https://example.com/api".to_string())
+        );
+        assert!(parse("htp://localhost").unwrap_err().contains("htp"));
+        assert!(parse("localhost:8080")
+            .unwrap_err()
+            .contains("http://localhost:8080"));
+        assert!(parse("ftp://example.com").is_err());
+        assert!(parse("http://example.com/?a=1").is_err());
+    }
+}
@@ -0,0 +1,255 @@
+use crate::hash::{HashAlgorithm, Hasher};
+use crate::http::HttpClient;
+use crate::report::{CsvReport, CsvRow};
+use crate::summary::OperationStats;
+use crate::DownloadError;
+use bytes::Bytes;
+use reqwest::multipart::{Form, Part};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+// The file sent by every upload of an async run, read into memory once and shared by all requests
+pub struct AsyncUpload {
+    pub url: String,
+    pub body: Bytes,
+    // PUT the raw bytes instead of posting a multipart form
+    pub put: bool,
+    pub field_name: String,
+    pub file_name: String,
+    pub content_type: Option<String>,
+}
+
+// Settings of a run of concurrent transfers with --async
+pub struct AsyncRun {
+    // Name of the transferred file, as in the CSV report of the blocking path
+    pub file: String,
+    pub operations: usize,
+    // Transfers in flight at most, each operation holds a permit until it is done
+    pub concurrency: usize,
+    pub timeout: Duration,
+    pub upload: Option<AsyncUpload>,
+    pub download_url: Option<String>,
+    pub hash: HashAlgorithm,
+    // Hash every download must have, from --expected-hash or of the uploaded file in a roundtrip
+    pub expected_hash: Option<String>,
+    // Successful transfers that take longer are logged as slow
+    pub slow_threshold: Duration,
+}
+
+// Result of one upload or download
+struct Outcome {
+    operation: &'static str,
+    status: Option<u16>,
+    size: u64,
+    duration: Duration,
+    error: Option<DownloadError>,
+}
+
+// Runs the operations as tasks on a multi-threaded runtime, at most `concurrency` at a time.
+// Returns false if any transfer failed.
+pub fn run(client: HttpClient, run: AsyncRun, mut csv_report: Option<CsvReport>) -> bool {
+    let runtime = match tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+    {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            tracing::error!("Error starting the async runtime: {}", e);
+            return false;
+        }
+    };
+    let client = Arc::new(client);
+    let run = Arc::new(run);
+    tracing::info!(
+        "Running {} operations with up to {} in flight",
+        run.operations,
+        run.concurrency
+    );
+
+    let mut uploads = OperationStats::new("Uploads");
+    let mut downloads = OperationStats::new("Downloads");
+    let mut record = |outcomes: Vec<Outcome>| {
+        for outcome in outcomes {
+            let stats = match outcome.operation {
+                "upload" => &mut uploads,
+                _ => &mut downloads,
+            };
+            if let Some(report) = csv_report.as_mut() {
+                let status = match &outcome.error {
+                    None => outcome
+                        .status
+                        .map_or("error".to_string(), |s| s.to_string()),
+                    Some(e) => e.status().map_or("error".to_string(), |s| s.to_string()),
+                };
+                let mut row = CsvRow::new(outcome.operation, &run.file, status, outcome.duration);
+                row.size_bytes = Some(outcome.size);
+                report.write(row);
+            }
+            match outcome.error {
+                None => {
+                    crate::warn_if_slow(&run.file, outcome.duration, run.slow_threshold);
+                    stats.succeeded(outcome.duration, outcome.size)
+                }
+                Some(e) => {
+                    tracing::error!("Error in {}: {}", outcome.operation, e);
+                    stats.failed();
+                }
+            }
+        }
+    };
+
+    let start_time = Instant::now();
+    runtime.block_on(async {
+        let semaphore = Arc::new(Semaphore::new(run.concurrency));
+        let mut tasks = JoinSet::new();
+        for _ in 0..run.operations {
+            if crate::interrupted() {
+                tracing::warn!("Stopped starting operations, waiting for those in flight");
+                break;
+            }
+            let permit = Arc::clone(&semaphore)
+                .acquire_owned()
+                .await
+                .expect("semaphore closed");
+            let client = Arc::clone(&client);
+            let run = Arc::clone(&run);
+            tasks.spawn(async move {
+                let outcomes = operation(&client, &run).await;
+                drop(permit);
+                outcomes
+            });
+            // Finished operations are recorded right away so their results do not pile up
+            while let Some(result) = tasks.try_join_next() {
+                record(result.expect("transfer task panicked"));
+            }
+        }
+        while let Some(result) = tasks.join_next().await {
+            record(result.expect("transfer task panicked"));
+        }
+    });
+    let elapsed = start_time.elapsed();
+
+    tracing::info!("Finished in {:.2?}", elapsed);
+    uploads.log(elapsed);
+    downloads.log(elapsed);
+    uploads.failures() == 0 && downloads.failures() == 0
+}
+
+// The upload and/or download of one iteration
+async fn operation(client: &HttpClient, run: &AsyncRun) -> Vec<Outcome> {
+    let mut outcomes = Vec::new();
+    if let Some(upload) = &run.upload {
+        let start_time = Instant::now();
+        let result = upload_file(client, upload, run.timeout).await;
+        outcomes.push(outcome("upload", result, start_time));
+    }
+    if let Some(url) = &run.download_url {
+        let start_time = Instant::now();
+        let result = download_file(client, url, run).await;
+        outcomes.push(outcome("download", result, start_time));
+    }
+    outcomes
+}
+
+fn outcome(
+    operation: &'static str,
+    result: Result<(u16, u64), DownloadError>,
+    start_time: Instant,
+) -> Outcome {
+    let duration = start_time.elapsed();
+    match result {
+        Ok((status, size)) => Outcome {
+            operation,
+            status: Some(status),
+            size,
+            duration,
+            error: None,
+        },
+        Err(e) => Outcome {
+            operation,
+            status: None,
+            size: 0,
+            duration,
+            error: Some(e),
+        },
+    }
+}
+
+fn async_client(client: &HttpClient) -> &reqwest::Client {
+    client
+        .async_client()
+        .expect("the async client is built for --async")
+}
+
+// Error for a status the client does not accept, naming the expected ones with --expect-status
+fn rejected_status(client: &HttpClient, status: reqwest::StatusCode) -> DownloadError {
+    crate::status_mismatch(client, status.as_u16())
+        .unwrap_or(DownloadError::Status(status.as_u16()))
+}
+
+async fn upload_file(
+    client: &HttpClient,
+    upload: &AsyncUpload,
+    timeout: Duration,
+) -> Result<(u16, u64), DownloadError> {
+    let request = if upload.put {
+        async_client(client)
+            .put(&upload.url)
+            .header(
+                reqwest::header::CONTENT_TYPE,
+                upload
+                    .content_type
+                    .as_deref()
+                    .unwrap_or("application/octet-stream"),
+            )
+            .body(upload.body.clone())
+    } else {
+        let mut part = Part::stream_with_length(upload.body.clone(), upload.body.len() as u64)
+            .file_name(upload.file_name.clone());
+        if let Some(content_type) = &upload.content_type {
+            part = part.mime_str(content_type)?;
+        }
+        async_client(client)
+            .post(&upload.url)
+            .multipart(Form::new().part(upload.field_name.clone(), part))
+    };
+    let response = client.send_async(request.timeout(timeout)).await?;
+    let status = response.status();
+    // Read to the end so the connection can be reused
+    let _ = response.bytes().await;
+    if !client.accepts(status) {
+        return Err(rejected_status(client, status));
+    }
+    Ok((status.as_u16(), upload.body.len() as u64))
+}
+
+// Streams the response through the hasher, the data itself is not kept
+async fn download_file(
+    client: &HttpClient,
+    url: &str,
+    run: &AsyncRun,
+) -> Result<(u16, u64), DownloadError> {
+    let request = async_client(client).get(url).timeout(run.timeout);
+    let mut response = client.send_async(request).await?;
+    let status = response.status();
+    if !client.accepts(status) {
+        return Err(rejected_status(client, status));
+    }
+
+    let mut hasher = run.hash.hasher();
+    let mut size = 0;
+    while let Some(chunk) = response.chunk().await? {
+        hasher.update(&chunk);
+        size += chunk.len() as u64;
+    }
+    let hash = hasher.finalize();
+    match &run.expected_hash {
+        Some(expected) if hash != *expected => Err(DownloadError::HashMismatch {
+            expected: expected.clone(),
+            actual: hash,
+        }),
+        _ => Ok((status.as_u16(), size)),
+    }
+}
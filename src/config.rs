@@ -0,0 +1,182 @@
+use clap::parser::ValueSource;
+use clap::ArgMatches;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub const CONFIG_FILE_NAME: &str = "simple-file-client.toml";
+
+// Defaults read from the config file, every setting is optional and command line flags take precedence
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: Option<String>,
+    pub timeout: Option<u64>,
+    pub connect_timeout: Option<u64>,
+    pub retries: Option<u32>,
+    pub proxy: Option<String>,
+    pub user_agent: Option<String>,
+    pub auth: AuthConfig,
+    pub tls: TlsConfig,
+    // Named [profiles.<name>] tables overriding the settings above, selected with --profile
+    pub profiles: BTreeMap<String, Config>,
+}
+
+// Basic (username/password) or bearer token credentials
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub token: Option<String>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsConfig {
+    // Verify server certificates, off unless enabled
+    pub verify: Option<bool>,
+    // PEM file with an additional trusted root certificate
+    pub ca_cert: Option<PathBuf>,
+}
+
+impl Config {
+    pub fn parse(text: &str) -> Result<Self, toml::de::Error> {
+        toml::from_str(text)
+    }
+
+    // Loads an explicitly requested file, which must exist, or else the first of
+    // ./simple-file-client.toml and $HOME/.config/simple-file-client.toml that exists
+    pub fn load(explicit: Option<&Path>) -> io::Result<Self> {
+        let path = match explicit {
+            Some(path) => Some(path.to_path_buf()),
+            None => default_locations().into_iter().find(|path| path.is_file()),
+        };
+        let Some(path) = path else {
+            return Ok(Config::default());
+        };
+
+        let text = std::fs::read_to_string(&path)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+        let config = Self::parse(&text).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {}", path.display(), e),
+            )
+        })?;
+        tracing::debug!("Loaded config file {}", path.display());
+        Ok(config)
+    }
+
+    // Applies the settings of a named profile on top of the top-level ones
+    pub fn select_profile(&mut self, name: &str) -> Result<(), String> {
+        let Some(profile) = self.profiles.remove(name) else {
+            let available: Vec<&str> = self.profiles.keys().map(String::as_str).collect();
+            return Err(format!(
+                "unknown profile {:?}, available profiles: {}",
+                name,
+                if available.is_empty() {
+                    "none".to_string()
+                } else {
+                    available.join(", ")
+                }
+            ));
+        };
+        if !profile.profiles.is_empty() {
+            return Err(format!("profile {:?} must not contain profiles", name));
+        }
+
+        self.server = profile.server.or(self.server.take());
+        self.timeout = profile.timeout.or(self.timeout);
+        self.connect_timeout = profile.connect_timeout.or(self.connect_timeout);
+        self.retries = profile.retries.or(self.retries);
+        self.proxy = profile.proxy.or(self.proxy.take());
+        self.user_agent = profile.user_agent.or(self.user_agent.take());
+        // Credentials are replaced as a whole so a profile token is not shadowed by a default username
+        if !profile.auth.is_empty() {
+            self.auth = profile.auth;
+        }
+        self.tls.verify = profile.tls.verify.or(self.tls.verify);
+        self.tls.ca_cert = profile.tls.ca_cert.or(self.tls.ca_cert.take());
+        Ok(())
+    }
+}
+
+impl AuthConfig {
+    fn is_empty(&self) -> bool {
+        self.username.is_none() && self.password.is_none() && self.token.is_none()
+    }
+}
+
+fn default_locations() -> Vec<PathBuf> {
+    let mut locations = vec![PathBuf::from(CONFIG_FILE_NAME)];
+    if let Some(home) = std::env::var_os("HOME") {
+        locations.push(Path::new(&home).join(".config").join(CONFIG_FILE_NAME));
+    }
+    locations
+}
+
+// True if the argument was given on the command line rather than taken from its default value
+pub fn is_explicit(matches: &ArgMatches, id: &str) -> bool {
+    matches!(matches.value_source(id), Some(source) if source != ValueSource::DefaultValue)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config_file_parsing() {
+        let config = Config::parse(
+            r#"
+            server = "https://files.example.com"
+            timeout = 60
+            connect_timeout = 5
+
+            [auth]
+            token = "abc"
+
+            [tls]
+            ca_cert = "root.pem"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.server.as_deref(), Some("https://files.example.com"));
+        assert_eq!(config.timeout, Some(60));
+        assert_eq!(config.connect_timeout, Some(5));
+        assert_eq!(config.retries, None);
+        assert_eq!(config.auth.token.as_deref(), Some("abc"));
+        assert_eq!(config.tls.ca_cert, Some(PathBuf::from("root.pem")));
+
+        // Typos are reported instead of being silently ignored, also inside profiles
+        assert!(Config::parse("sever = \"http://localhost\"").is_err());
+        assert!(Config::parse("[profiles.dev]\nsever = \"http://localhost\"").is_err());
+    }
+
+    #[test]
+    fn config_profile_overrides_defaults() {
+        let mut config = Config::parse(
+            r#"
+            server = "https://prod.example.com"
+            timeout = 60
+            [auth]
+            username = "alice"
+            password = "secret"
+
+            [profiles.dev]
+            server = "http://localhost:3000"
+            [profiles.dev.auth]
+            token = "dev-token"
+            "#,
+        )
+        .unwrap();
+        assert!(config.select_profile("staging").is_err());
+
+        config.select_profile("dev").unwrap();
+        assert_eq!(config.server.as_deref(), Some("http://localhost:3000"));
+        assert_eq!(config.timeout, Some(60));
+        assert_eq!(config.auth.username, None);
+        assert_eq!(config.auth.token.as_deref(), Some("dev-token"));
+    }
+}
@@ -0,0 +1,282 @@
+use reqwest::cookie::CookieStore;
+use reqwest::header::HeaderValue;
+use reqwest::Url;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+// A cookie as stored in a Netscape cookie file
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cookie {
+    // Host name without a leading dot
+    pub domain: String,
+    // Also sent to subdomains, true for cookies set with a Domain attribute
+    pub include_subdomains: bool,
+    pub path: String,
+    pub secure: bool,
+    // Unix time, 0 for session cookies
+    pub expires: i64,
+    pub name: String,
+    pub value: String,
+}
+
+impl Cookie {
+    // Parses a Set-Cookie header value received for `url`, None if it is malformed or for another domain
+    pub fn parse_set_cookie(header: &str, url: &Url) -> Option<Self> {
+        let host = url.host_str()?.to_ascii_lowercase();
+        let mut attributes = header.split(';');
+        let (name, value) = attributes.next()?.split_once('=')?;
+        let mut cookie = Cookie {
+            domain: host.clone(),
+            include_subdomains: false,
+            path: default_path(url),
+            secure: false,
+            expires: 0,
+            name: name.trim().to_string(),
+            value: value.trim().trim_matches('"').to_string(),
+        };
+        if cookie.name.is_empty() {
+            return None;
+        }
+
+        let mut max_age = None;
+        for attribute in attributes {
+            let (key, value) = attribute.split_once('=').unwrap_or((attribute, ""));
+            let value = value.trim();
+            match key.trim().to_ascii_lowercase().as_str() {
+                "domain" if !value.is_empty() => {
+                    let domain = value.trim_start_matches('.').to_ascii_lowercase();
+                    // A server may only set cookies for its own domain or a parent of it
+                    if !domain_matches(&host, &domain) {
+                        return None;
+                    }
+                    cookie.domain = domain;
+                    cookie.include_subdomains = true;
+                }
+                "path" if value.starts_with('/') => cookie.path = value.to_string(),
+                "secure" => cookie.secure = true,
+                "expires" => {
+                    if let Ok(expires) = chrono::DateTime::parse_from_rfc2822(value) {
+                        // 0 would make it a session cookie
+                        cookie.expires = expires.timestamp().max(1);
+                    }
+                }
+                "max-age" => max_age = value.parse::<i64>().ok(),
+                _ => {}
+            }
+        }
+        // Max-Age takes precedence over Expires, zero or less deletes the cookie
+        if let Some(max_age) = max_age {
+            cookie.expires = match max_age {
+                ..=0 => 1,
+                _ => chrono::Utc::now().timestamp().saturating_add(max_age),
+            };
+        }
+        Some(cookie)
+    }
+
+    fn is_expired(&self, now: i64) -> bool {
+        self.expires != 0 && self.expires <= now
+    }
+
+    fn matches(&self, url: &Url) -> bool {
+        let Some(host) = url.host_str() else {
+            return false;
+        };
+        let host = host.to_ascii_lowercase();
+        let domain_ok = if self.include_subdomains {
+            domain_matches(&host, &self.domain)
+        } else {
+            host == self.domain
+        };
+        domain_ok
+            && path_matches(url.path(), &self.path)
+            && (!self.secure || url.scheme() == "https")
+    }
+
+    fn same_slot(&self, other: &Cookie) -> bool {
+        self.domain == other.domain && self.path == other.path && self.name == other.name
+    }
+}
+
+// Directory of the request path, the path a cookie without a Path attribute is sent for
+fn default_path(url: &Url) -> String {
+    match url.path().rfind('/') {
+        Some(0) | None => "/".to_string(),
+        Some(end) => url.path()[..end].to_string(),
+    }
+}
+
+fn domain_matches(host: &str, domain: &str) -> bool {
+    host == domain || host.ends_with(&format!(".{}", domain))
+}
+
+fn path_matches(request_path: &str, cookie_path: &str) -> bool {
+    request_path == cookie_path
+        || (request_path.starts_with(cookie_path)
+            && (cookie_path.ends_with('/') || request_path[cookie_path.len()..].starts_with('/')))
+}
+
+// In-memory cookie store of the HTTP client, optionally loaded from and saved to a Netscape cookie file
+pub struct CookieJar {
+    cookies: Mutex<Vec<Cookie>>,
+    // Given with --cookie, sent with every request but never saved
+    manual: Vec<(String, String)>,
+    // Rewritten whenever the server changes a cookie, so the jar survives an aborted run
+    file: Option<PathBuf>,
+}
+
+impl CookieJar {
+    pub fn new(manual: Vec<(String, String)>, file: Option<&Path>) -> io::Result<Self> {
+        let cookies = match file {
+            Some(path) if path.exists() => read_netscape(path)?,
+            _ => Vec::new(),
+        };
+        Ok(Self {
+            cookies: Mutex::new(cookies),
+            manual,
+            file: file.map(Path::to_path_buf),
+        })
+    }
+
+    fn save(&self, cookies: &[Cookie]) {
+        let Some(path) = &self.file else {
+            return;
+        };
+        if let Err(e) = write_netscape(path, cookies) {
+            tracing::warn!("Error writing cookie jar {}: {}", path.display(), e);
+        }
+    }
+}
+
+impl CookieStore for CookieJar {
+    fn set_cookies(&self, cookie_headers: &mut dyn Iterator<Item = &HeaderValue>, url: &Url) {
+        let mut cookies = self.cookies.lock().unwrap_or_else(|e| e.into_inner());
+        let now = chrono::Utc::now().timestamp();
+        let mut changed = false;
+        for header in cookie_headers {
+            let Some(cookie) = header
+                .to_str()
+                .ok()
+                .and_then(|header| Cookie::parse_set_cookie(header, url))
+            else {
+                continue;
+            };
+            tracing::debug!("Received cookie {} for {}", cookie.name, cookie.domain);
+            cookies.retain(|existing| !existing.same_slot(&cookie));
+            if !cookie.is_expired(now) {
+                cookies.push(cookie);
+            }
+            changed = true;
+        }
+        if changed {
+            self.save(&cookies);
+        }
+    }
+
+    fn cookies(&self, url: &Url) -> Option<HeaderValue> {
+        let cookies = self.cookies.lock().unwrap_or_else(|e| e.into_inner());
+        let now = chrono::Utc::now().timestamp();
+        let pairs: Vec<String> = self
+            .manual
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, value))
+            .chain(
+                cookies
+                    .iter()
+                    .filter(|cookie| !cookie.is_expired(now) && cookie.matches(url))
+                    .map(|cookie| format!("{}={}", cookie.name, cookie.value)),
+            )
+            .collect();
+        if pairs.is_empty() {
+            return None;
+        }
+        HeaderValue::from_str(&pairs.join("; ")).ok()
+    }
+}
+
+// Reads a Netscape cookie file as written by curl and browsers, expired cookies are dropped
+pub fn read_netscape(path: &Path) -> io::Result<Vec<Cookie>> {
+    let reader = BufReader::new(File::open(path)?);
+    let now = chrono::Utc::now().timestamp();
+    let mut cookies = Vec::new();
+
+    for line in reader.lines() {
+        let line = line?;
+        // curl marks HttpOnly cookies with a prefix that otherwise looks like a comment
+        let line = line.strip_prefix("#HttpOnly_").unwrap_or(&line);
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        let [domain, include_subdomains, path, secure, expires, name, value] = fields[..] else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Malformed cookie line: {}", line),
+            ));
+        };
+        let cookie = Cookie {
+            domain: domain.trim_start_matches('.').to_ascii_lowercase(),
+            include_subdomains: include_subdomains.eq_ignore_ascii_case("TRUE"),
+            path: path.to_string(),
+            secure: secure.eq_ignore_ascii_case("TRUE"),
+            expires: expires.parse().unwrap_or(0),
+            name: name.to_string(),
+            value: value.to_string(),
+        };
+        if !cookie.is_expired(now) {
+            cookies.push(cookie);
+        }
+    }
+    Ok(cookies)
+}
+
+pub fn write_netscape(path: &Path, cookies: &[Cookie]) -> io::Result<()> {
+    let mut file = File::create(path)?;
+    writeln!(file, "# Netscape HTTP Cookie File")?;
+    for cookie in cookies {
+        let flag = |value: bool| if value { "TRUE" } else { "FALSE" };
+        writeln!(
+            file,
+            "{}{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            if cookie.include_subdomains { "." } else { "" },
+            cookie.domain,
+            flag(cookie.include_subdomains),
+            cookie.path,
+            flag(cookie.secure),
+            cookie.expires,
+            cookie.name,
+            cookie.value
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempPath;
+
+    #[test]
+    fn cookie_jar_round_trip() {
+        let url = reqwest::Url::parse("http://files.example.com/upload/form").unwrap();
+        let scoped =
+            Cookie::parse_set_cookie("session=abc; Domain=example.com; Secure", &url).unwrap();
+        assert_eq!(scoped.domain, "example.com");
+        assert!(scoped.include_subdomains && scoped.secure);
+        assert_eq!(scoped.path, "/upload");
+        assert!(Cookie::parse_set_cookie("a=1; Domain=other.com", &url).is_none());
+
+        let host_only =
+            Cookie::parse_set_cookie("token=\"xyz\"; Path=/; Max-Age=60", &url).unwrap();
+        assert_eq!(host_only.value, "xyz");
+        assert!(host_only.expires > 0);
+
+        let path = TempPath::new("cookies.txt");
+        let saved = vec![scoped, host_only];
+        write_netscape(&path, &saved).unwrap();
+        let loaded = read_netscape(&path).unwrap();
+        assert_eq!(loaded, saved);
+    }
+}
@@ -0,0 +1,193 @@
+use blake3::hazmat::{
+    left_subtree_len, merge_subtrees_non_root, merge_subtrees_root, ChainingValue, HasherExt, Mode,
+};
+use sha2::{Digest, Sha256, Sha512};
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+
+// Smallest part of a file hashed on its own thread, below that a thread costs more than it saves
+const MIN_PARALLEL_SEGMENT: u64 = 1024 * 1024;
+
+// Algorithm of the file hashes that are printed and compared
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HashAlgorithm {
+    Sha256,
+    Sha512,
+    Blake3,
+    // No hashing at all, for measuring pure transfer speed
+    None,
+}
+
+impl HashAlgorithm {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "sha256" => Some(HashAlgorithm::Sha256),
+            "sha512" => Some(HashAlgorithm::Sha512),
+            "blake3" => Some(HashAlgorithm::Blake3),
+            "none" => Some(HashAlgorithm::None),
+            _ => None,
+        }
+    }
+
+    // Label of printed hashes, e.g. "SHA256: <hex>"
+    pub fn label(self) -> &'static str {
+        match self {
+            HashAlgorithm::Sha256 => "SHA256",
+            HashAlgorithm::Sha512 => "SHA512",
+            HashAlgorithm::Blake3 => "BLAKE3",
+            HashAlgorithm::None => "Hash",
+        }
+    }
+
+    // Extension of checksum sidecar files, as in data.bin.sha256
+    pub fn extension(self) -> Option<&'static str> {
+        match self {
+            HashAlgorithm::Sha256 => Some("sha256"),
+            HashAlgorithm::Sha512 => Some("sha512"),
+            HashAlgorithm::Blake3 => Some("blake3"),
+            HashAlgorithm::None => None,
+        }
+    }
+
+    pub fn hasher(self) -> FileHasher {
+        match self {
+            HashAlgorithm::Sha256 => FileHasher::Sha256(Sha256::new()),
+            HashAlgorithm::Sha512 => FileHasher::Sha512(Sha512::new()),
+            HashAlgorithm::Blake3 => FileHasher::Blake3(Box::new(blake3::Hasher::new())),
+            HashAlgorithm::None => FileHasher::None,
+        }
+    }
+}
+
+// Incremental hash of a file, finalized to lowercase hex
+pub trait Hasher {
+    fn update(&mut self, data: &[u8]);
+    fn finalize(self) -> String;
+}
+
+impl Hasher for Sha256 {
+    fn update(&mut self, data: &[u8]) {
+        Digest::update(self, data);
+    }
+
+    fn finalize(self) -> String {
+        hex::encode(Digest::finalize(self))
+    }
+}
+
+impl Hasher for Sha512 {
+    fn update(&mut self, data: &[u8]) {
+        Digest::update(self, data);
+    }
+
+    fn finalize(self) -> String {
+        hex::encode(Digest::finalize(self))
+    }
+}
+
+impl Hasher for blake3::Hasher {
+    fn update(&mut self, data: &[u8]) {
+        blake3::Hasher::update(self, data);
+    }
+
+    fn finalize(self) -> String {
+        blake3::Hasher::finalize(&self).to_hex().to_string()
+    }
+}
+
+// Hasher of the algorithm chosen at runtime, `None` ignores the data and finalizes to "-"
+#[derive(Clone)]
+pub enum FileHasher {
+    Sha256(Sha256),
+    Sha512(Sha512),
+    Blake3(Box<blake3::Hasher>),
+    None,
+}
+
+impl Hasher for FileHasher {
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            FileHasher::Sha256(hasher) => Hasher::update(hasher, data),
+            FileHasher::Sha512(hasher) => Hasher::update(hasher, data),
+            FileHasher::Blake3(hasher) => Hasher::update(hasher.as_mut(), data),
+            FileHasher::None => {}
+        }
+    }
+
+    fn finalize(self) -> String {
+        match self {
+            FileHasher::Sha256(hasher) => Hasher::finalize(hasher),
+            FileHasher::Sha512(hasher) => Hasher::finalize(hasher),
+            FileHasher::Blake3(hasher) => Hasher::finalize(*hasher),
+            FileHasher::None => "-".to_string(),
+        }
+    }
+}
+
+// BLAKE3 of a file hashed on up to `threads` threads. BLAKE3 is a tree of chunks, so subtrees are
+// hashed independently and merged into the same hash a sequential pass produces. SHA-2 has no
+// such structure, every block depends on all blocks before it.
+pub fn parallel_blake3(path: &Path, threads: usize) -> io::Result<String> {
+    let len = std::fs::metadata(path)?.len();
+    if threads <= 1 || len <= MIN_PARALLEL_SEGMENT {
+        let mut hasher = blake3::Hasher::new();
+        hash_segment(path, &mut hasher, 0, len)?;
+        return Ok(Hasher::finalize(hasher));
+    }
+
+    let left_len = left_subtree_len(len);
+    let (left, right) = join(
+        || subtree(path, 0, left_len, threads - threads / 2),
+        || subtree(path, left_len, len - left_len, threads / 2),
+    );
+    Ok(merge_subtrees_root(&left?, &right?, Mode::Hash)
+        .to_hex()
+        .to_string())
+}
+
+// Chaining value of the subtree covering `len` bytes at `offset`, split the same way as the root
+fn subtree(path: &Path, offset: u64, len: u64, threads: usize) -> io::Result<ChainingValue> {
+    if threads <= 1 || len <= MIN_PARALLEL_SEGMENT {
+        let mut hasher = blake3::Hasher::new();
+        hasher.set_input_offset(offset);
+        hash_segment(path, &mut hasher, offset, len)?;
+        return Ok(hasher.finalize_non_root());
+    }
+
+    let left_len = left_subtree_len(len);
+    let (left, right) = join(
+        || subtree(path, offset, left_len, threads - threads / 2),
+        || subtree(path, offset + left_len, len - left_len, threads / 2),
+    );
+    Ok(merge_subtrees_non_root(&left?, &right?, Mode::Hash))
+}
+
+// Runs `right` on a new thread and `left` on the current one
+fn join<L, R>(left: impl FnOnce() -> L, right: impl FnOnce() -> R + Send) -> (L, R)
+where
+    R: Send,
+{
+    std::thread::scope(|scope| {
+        let right = scope.spawn(right);
+        let left = left();
+        (left, right.join().unwrap())
+    })
+}
+
+// Each segment opens the file itself, so reads of different threads do not share a position
+fn hash_segment(path: &Path, hasher: &mut blake3::Hasher, offset: u64, len: u64) -> io::Result<()> {
+    let mut file = File::open(path)?;
+    file.seek(SeekFrom::Start(offset))?;
+    let mut reader = file.take(len);
+    let mut buffer = vec![0u8; 64 * 1024];
+
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        blake3::Hasher::update(hasher, &buffer[..read]);
+    }
+    Ok(())
+}
@@ -1,304 +1,4394 @@
-use chrono::Local;
-use clap::{Arg, Command};
-use rand::{distributions::Alphanumeric, Rng};
-use reqwest::blocking::{ClientBuilder, Response};
-use sha2::{Digest, Sha256};
+mod cache;
+mod cli;
+mod concurrent;
+mod config;
+mod cookies;
+mod hash;
+mod http;
+mod manifest;
+mod output;
+mod progress;
+mod report;
+mod summary;
+#[cfg(test)]
+mod testing;
+mod throttle;
+mod timing;
+mod writeout;
+
+use base64::Engine;
+use cache::{CacheEntry, DownloadCache};
+use clap::ArgMatches;
+use colored::Colorize;
+use config::{is_explicit, AuthConfig, Config};
+use cookies::CookieJar;
+use hash::{FileHasher, HashAlgorithm, Hasher};
+use http::{log_resolved_addresses, ClientOptions, HttpClient, HttpVersion, BODY_LOG_LIMIT};
+use indicatif::ProgressBar;
+use manifest::{Manifest, ManifestEntry};
+use output::{human_size, human_throughput, DEFAULT_TIME_FORMAT};
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
+use progress::{finish_bar, transfer_bar, ProgressReader};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use report::{CsvReport, CsvRow};
+use reqwest::blocking::multipart::Part;
+use reqwest::blocking::Response;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, InvalidHeaderValue, AUTHORIZATION};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::HashSet;
 use std::fs::File;
-use std::io::{self, Read, Write};
-use std::path::Path;
+use std::io::{self, BufRead, BufReader, BufWriter, IsTerminal, Read, Seek, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
+use summary::OperationStats;
 use thiserror::Error;
+use throttle::{SpeedMonitor, ThrottledReader, TokenBucket};
+use timing::PhaseTimings;
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::time::{ChronoLocal, ChronoUtc, FormatTime};
+use tracing_subscriber::fmt::writer::{BoxMakeWriter, MakeWriterExt};
+use tracing_subscriber::EnvFilter;
+use writeout::{Variable, WriteOut, WriteOutValues};
 
 // Define a custom error type
 #[derive(Error, Debug)]
 pub enum DownloadError {
-    #[error("Network error")]
-    Network(#[from] reqwest::Error),
+    #[error("Request timed out: {0}")]
+    Timeout(reqwest::Error),
+
+    #[error("Unable to connect: {0}")]
+    Connect(reqwest::Error),
+
+    #[error("Unexpected status: {}", status_text(*.0))]
+    Status(u16),
+
+    #[error("Unexpected status: {}, server said: {body}", status_text(*status))]
+    ErrorResponse { status: u16, body: String },
+
+    // The downloaded file does not have the hash given with --expected-hash
+    #[error("Hash verification: FAILED (expected {expected}, got {actual})")]
+    HashMismatch { expected: String, actual: String },
+
+    // A status other than the ones given with --expect-status, which may well be a 2xx status
+    #[error("Expected status {expected}, got {}", status_text(*status))]
+    StatusMismatch { expected: String, status: u16 },
+
+    // Only seen when redirects are not followed (--no-follow or --max-redirects 0)
+    #[error("Redirected ({}) to {location}", status_text(*status))]
+    Redirected { status: u16, location: String },
+
+    // A redirect chain longer than --max-redirects, or a redirect that could not be followed
+    #[error("Redirect error: {0}")]
+    Redirect(reqwest::Error),
 
-    #[error("IO error")]
-    Io(#[from] io::Error),
+    #[error("Error reading the response body: {0}")]
+    Body(reqwest::Error),
+
+    #[error("Network error: {0}")]
+    Network(reqwest::Error),
+
+    #[error("IO error: {0}")]
+    Io(io::Error),
+
+    #[error("Missing or invalid Content-Length header")]
+    ContentLength,
+
+    #[error("Download too slow: {measured_bps} B/s, required {required_bps} B/s")]
+    SpeedTooLow {
+        measured_bps: u64,
+        required_bps: u64,
+    },
 }
 
-fn generate_random_text_file(filename: &Path, size: usize) -> io::Result<String> {
-    if filename.exists() && filename.metadata()?.len() as usize == size {
-        println!(
-            "File: {:?} already exists with the correct size of {} bytes.",
-            filename, size
-        );
-        return Ok(hex::encode(Sha256::digest(&std::fs::read(filename)?)));
+impl DownloadError {
+    // Status of the response the error is about, None if there was no response
+    fn status(&self) -> Option<u16> {
+        match self {
+            DownloadError::Status(status)
+            | DownloadError::ErrorResponse { status, .. }
+            | DownloadError::StatusMismatch { status, .. }
+            | DownloadError::Redirected { status, .. } => Some(*status),
+            _ => None,
+        }
+    }
+}
+
+// Classifies a reqwest error by the stage of the exchange that failed
+impl From<reqwest::Error> for DownloadError {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_timeout() {
+            DownloadError::Timeout(e)
+        } else if e.is_connect() {
+            DownloadError::Connect(e)
+        } else if let Some(status) = e.status() {
+            DownloadError::Status(status.as_u16())
+        } else if e.is_redirect() {
+            DownloadError::Redirect(e)
+        } else if e.is_body() || e.is_decode() {
+            DownloadError::Body(e)
+        } else {
+            DownloadError::Network(e)
+        }
+    }
+}
+
+// Response bodies are read through `Read`, which wraps reqwest errors in an io::Error
+impl From<io::Error> for DownloadError {
+    fn from(e: io::Error) -> Self {
+        if !e
+            .get_ref()
+            .is_some_and(|inner| inner.is::<reqwest::Error>())
+        {
+            return DownloadError::Io(e);
+        }
+        match e
+            .into_inner()
+            .map(|inner| inner.downcast::<reqwest::Error>())
+        {
+            Some(Ok(inner)) => (*inner).into(),
+            _ => unreachable!("the inner error was checked to be a reqwest error"),
+        }
+    }
+}
+
+// "404 Not Found", or just the code for unknown statuses
+fn status_text(code: u16) -> String {
+    match reqwest::StatusCode::from_u16(code) {
+        Ok(status) => status.to_string(),
+        Err(_) => code.to_string(),
+    }
+}
+
+// Longest part of an error response body that is included in error messages
+const ERROR_BODY_LIMIT: usize = 2048;
+
+// Error for a non-success response, with the start of the body since servers often explain the
+// failure there
+fn status_error(response: Response) -> DownloadError {
+    let status = response.status().as_u16();
+    if let Some(location) = response
+        .headers()
+        .get(reqwest::header::LOCATION)
+        .filter(|_| response.status().is_redirection())
+        .and_then(|value| value.to_str().ok())
+    {
+        return DownloadError::Redirected {
+            status,
+            location: location.to_string(),
+        };
+    }
+    let mut body = Vec::new();
+    if response
+        .take(ERROR_BODY_LIMIT as u64 + 1)
+        .read_to_end(&mut body)
+        .is_err()
+    {
+        return DownloadError::Status(status);
+    }
+    let truncated = body.len() > ERROR_BODY_LIMIT;
+    body.truncate(ERROR_BODY_LIMIT);
+    match String::from_utf8_lossy(&body).trim() {
+        "" => DownloadError::Status(status),
+        text if truncated => DownloadError::ErrorResponse {
+            status,
+            body: format!("{}... (truncated)", text),
+        },
+        text => DownloadError::ErrorResponse {
+            status,
+            body: text.to_string(),
+        },
+    }
+}
+
+// Error for a status the client does not accept
+fn rejected_status(client: &HttpClient, response: Response) -> DownloadError {
+    status_mismatch(client, response.status().as_u16()).unwrap_or_else(|| status_error(response))
+}
+
+// The expected and the actual status when --expect-status is given
+fn status_mismatch(client: &HttpClient, status: u16) -> Option<DownloadError> {
+    match client.expected_statuses() {
+        [] => None,
+        expected => Some(DownloadError::StatusMismatch {
+            expected: expected
+                .iter()
+                .map(u16::to_string)
+                .collect::<Vec<_>>()
+                .join(" or "),
+            status,
+        }),
+    }
+}
+
+fn check_status(client: &HttpClient, response: Response) -> Result<Response, DownloadError> {
+    if client.accepts(response.status()) {
+        Ok(response)
+    } else {
+        Err(rejected_status(client, response))
+    }
+}
+
+// A file entry as reported by the server's listing endpoint
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ServerFile {
+    pub name: String,
+    pub size: u64,
+    pub sha256: Option<String>,
+    pub created_at: Option<String>,
+}
+
+// Kind of data written by the file generator
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ContentKind {
+    // Alphanumeric characters only
+    Text,
+    // Uniformly random bytes over the full 0..=255 range
+    Binary,
+}
+
+// Settings controlling the data written by the file generator
+#[derive(Clone, Debug)]
+pub struct GenerateOptions {
+    pub seed: u64,
+    pub content: ContentKind,
+    // Percentage (0..=100) of each block that is zero-filled, the rest is random data
+    pub compressibility: u8,
+    // Regenerate even if the file already exists with the requested size
+    pub force: bool,
+    // Advanced by the bytes generated, hidden unless progress is shown
+    pub progress: ProgressBar,
+    // Bytes produced, hashed and written per step, the compressibility is applied per block
+    pub block_size: usize,
+    pub hash: HashAlgorithm,
+    // Threads hashing the finished file with BLAKE3, which is then not hashed while generating
+    pub hash_threads: usize,
+}
+
+// How much result output goes to stdout, errors are always reported on stderr
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Verbosity {
+    Normal,
+    // -q: only the bare result of a command (a SHA256 or OK/FAIL), `list --json` and failures
+    Quiet,
+    // -qq: nothing, the exit code is the result
+    Silent,
+}
+
+impl Verbosity {
+    fn from_matches(matches: &ArgMatches) -> Self {
+        match matches.get_count("quiet") {
+            0 => Verbosity::Normal,
+            1 => Verbosity::Quiet,
+            _ => Verbosity::Silent,
+        }
+    }
+
+    // Human-readable results such as hashes and tables
+    fn prints_text(self) -> bool {
+        self == Verbosity::Normal
+    }
+
+    // Failure reports such as checksum mismatches and the `list --json` listing, kept with -q
+    fn prints_reports(self) -> bool {
+        self != Verbosity::Silent
+    }
+
+    // Bare results for scripts, printed instead of the human-readable ones
+    fn prints_bare(self) -> bool {
+        self == Verbosity::Quiet
+    }
+}
+
+// Default size of the blocks a file is generated in, large enough that per-block calls do not dominate
+const GENERATE_BLOCK_SIZE: usize = 1024 * 1024;
+
+const ALPHANUMERIC: &[u8; 62] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789";
+
+// The same size and options always produce byte-identical content
+fn generate_random_text_file(
+    filename: &Path,
+    size: usize,
+    options: &GenerateOptions,
+) -> io::Result<String> {
+    if filename.exists() {
+        let existing_size = filename.metadata()?.len() as usize;
+        if existing_size != size {
+            tracing::info!(
+                "File: {:?} already exists with a size of {} instead of {}, regenerating.",
+                filename,
+                human_size(existing_size as u64),
+                human_size(size as u64)
+            );
+        } else if options.force {
+            tracing::info!(
+                "File: {:?} already exists, regenerating (forced).",
+                filename
+            );
+        } else {
+            tracing::info!(
+                "File: {:?} already exists with the correct size of {}.",
+                filename,
+                human_size(size as u64)
+            );
+            options.progress.inc(size as u64);
+            return hash_file_on(filename, options.hash, options.hash_threads);
+        }
     }
 
-    let mut file = File::create(filename)?;
+    let mut file = BufWriter::new(File::create(filename)?);
     let mut generated_size = 0;
-    let block_size = 1024;
-    let mut hasher = Sha256::new();
+    let mut block = vec![0u8; options.block_size];
+    let parallel_hash = options.hash == HashAlgorithm::Blake3 && options.hash_threads > 1;
+    let mut hasher = if parallel_hash {
+        HashAlgorithm::None.hasher()
+    } else {
+        options.hash.hasher()
+    };
+    let mut rng = StdRng::seed_from_u64(options.seed);
 
     while generated_size < size {
         let remaining = size - generated_size;
-        let chunk_size = std::cmp::min(block_size, remaining);
-        let block: String = rand::thread_rng()
-            .sample_iter(&Alphanumeric)
-            .take(chunk_size)
-            .map(char::from)
-            .collect();
+        let chunk_size = std::cmp::min(options.block_size, remaining);
+        let block = &mut block[..chunk_size];
+        let zeroed = chunk_size * usize::from(options.compressibility) / 100;
+        block[..zeroed].fill(0);
+
+        let random = &mut block[zeroed..];
+        rng.fill(random);
+        if options.content == ContentKind::Text {
+            // The top 6 bits index the alphabet, values past its end are redrawn to keep it uniform
+            for b in random.iter_mut() {
+                let mut index = *b >> 2;
+                while usize::from(index) >= ALPHANUMERIC.len() {
+                    index = rng.gen::<u8>() >> 2;
+                }
+                *b = ALPHANUMERIC[usize::from(index)];
+            }
+        }
 
-        let block_bytes = block.as_bytes();
-        file.write_all(block_bytes)?;
-        hasher.update(block_bytes);
+        file.write_all(block)?;
+        hasher.update(block);
         generated_size += chunk_size;
+        options.progress.inc(chunk_size as u64);
     }
 
-    println!("Generated file: {:?}", filename);
-    Ok(hex::encode(hasher.finalize()))
+    file.flush()?;
+    // Log lines must not be drawn over the bar
+    options
+        .progress
+        .suspend(|| tracing::info!("Generated file: {:?}", filename));
+    if parallel_hash {
+        drop(file);
+        return hash_file_on(filename, options.hash, options.hash_threads);
+    }
+    Ok(hasher.finalize())
 }
 
-fn upload_file(
-    server_url: &str,
-    filename: &Path,
-    timeout_secs: u64,
-) -> Result<reqwest::blocking::Response, Box<dyn std::error::Error>> {
-    let client = ClientBuilder::new()
-        .danger_accept_invalid_certs(true)
-        .timeout(Duration::from_secs(timeout_secs)) // Set the timeout to the specified number of seconds
-        .build()?;
+// Hashes a file through a fixed-size buffer so memory use does not depend on the file size
+fn hash_file(filename: &Path, algorithm: HashAlgorithm) -> io::Result<String> {
+    // Not even read without hashing
+    if algorithm == HashAlgorithm::None {
+        return Ok(algorithm.hasher().finalize());
+    }
+    let mut hasher = algorithm.hasher();
+    hash_file_into(&mut hasher, filename)?;
 
-    let url = format!("{}/upload", server_url);
-    let form = reqwest::blocking::multipart::Form::new().file("file", filename)?; // Propagate the error instead of unwrapping
-    let response = client.post(url).multipart(form).send()?;
-    Ok(response)
+    let hash = hasher.finalize();
+    tracing::debug!("Computed {} of {:?}: {}", algorithm.label(), filename, hash);
+    Ok(hash)
 }
 
-fn download_file(
-    server_url: &str,
-    filename: &str,
-    chunked: bool,
-) -> Result<(usize, String), DownloadError> {
-    let client = ClientBuilder::new()
-        .danger_accept_invalid_certs(true)
-        .build()?;
-
-    let endpoint = if chunked {
-        "download-chunked"
-    } else {
-        "download"
-    };
-    let url = format!("{}/{}/{}", server_url, endpoint, filename);
-    let mut response = client.get(url).send()?;
+// Hashes a file like hash_file, BLAKE3 on up to `threads` threads
+fn hash_file_on(filename: &Path, algorithm: HashAlgorithm, threads: usize) -> io::Result<String> {
+    if algorithm != HashAlgorithm::Blake3 || threads <= 1 {
+        return hash_file(filename, algorithm);
+    }
+    let hash = hash::parallel_blake3(filename, threads)?;
+    tracing::debug!(
+        "Computed {} of {:?} on {} threads: {}",
+        algorithm.label(),
+        filename,
+        threads,
+        hash
+    );
+    Ok(hash)
+}
+
+// Feeds the contents of a file into a hasher
+fn hash_file_into(hasher: &mut impl Hasher, filename: &Path) -> io::Result<()> {
+    let mut file = File::open(filename)?;
+    let mut buffer = vec![0u8; 64 * 1024];
 
-    let mut hasher = Sha256::new();
-    let mut buffer = Vec::new();
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(())
+}
 
-    response.read_to_end(&mut buffer)?;
+// Checksum file kept next to a file, e.g. data.bin.sha256, None without hashing
+fn sidecar_path(path: &Path, algorithm: HashAlgorithm) -> Option<PathBuf> {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(".");
+    name.push(algorithm.extension()?);
+    Some(PathBuf::from(name))
+}
 
-    hasher.update(&buffer);
+// Writes the hash of a file to its sidecar in the `sha256sum` format. The entry has the bare file
+// name, so `sha256sum -c` works from the directory of the file.
+fn write_hash_sidecar(path: &Path, algorithm: HashAlgorithm, hash: &str) -> io::Result<()> {
+    let Some(sidecar) = sidecar_path(path, algorithm) else {
+        return Ok(());
+    };
+    let name = path.file_name().unwrap_or(path.as_os_str());
+    std::fs::write(&sidecar, format!("{}  {}\n", hash, name.to_string_lossy()))?;
+    tracing::debug!("Hash written to {}", sidecar.display());
+    Ok(())
+}
 
-    Ok((buffer.len(), hex::encode(hasher.finalize())))
+// Re-hashes a file and compares it against the hash in its sidecar
+fn read_and_verify_sidecar(path: &Path, algorithm: HashAlgorithm) -> io::Result<bool> {
+    let sidecar = sidecar_path(path, algorithm).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "no sidecar without a hash algorithm",
+        )
+    })?;
+    let content = std::fs::read_to_string(&sidecar)?;
+    let expected = content
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty() && !line.starts_with('#'))
+        .and_then(|line| line.split_whitespace().next())
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("No hash in {}", sidecar.display()),
+            )
+        })?;
+    Ok(hash_file(path, algorithm)?.eq_ignore_ascii_case(expected))
 }
 
-fn delete_file(server_url: &str, filename: &str) -> reqwest::Result<Response> {
-    let client = ClientBuilder::new()
-        .danger_accept_invalid_certs(true)
-        .build()?;
+// Verifies every entry of a `sha256sum`-style checksum file, returns true if all entries match
+fn check_checksum_file(
+    checksum_file: &Path,
+    algorithm: HashAlgorithm,
+    verbosity: Verbosity,
+) -> io::Result<bool> {
+    let reader = BufReader::new(File::open(checksum_file)?);
+    let mut all_ok = true;
+
+    for line in reader.lines() {
+        let line = line?;
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        // Each entry is "<hex digest>  <file>" or "<hex digest> *<file>" for binary mode
+        let Some((expected, name)) = line.split_once(char::is_whitespace) else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Malformed checksum line: {}", line),
+            ));
+        };
+        let name = name.trim_start_matches([' ', '*']);
+
+        match hash_file(Path::new(name), algorithm) {
+            Ok(actual) if actual.eq_ignore_ascii_case(expected) => {
+                if verbosity.prints_text() {
+                    println!("{}: {}", name, "OK".green());
+                }
+            }
+            Ok(_) => {
+                if verbosity.prints_reports() {
+                    println!("{}: {}", name, "MISMATCH".red());
+                }
+                all_ok = false;
+            }
+            Err(e) => {
+                if verbosity.prints_reports() {
+                    println!("{}: {} ({})", name, "MISMATCH".red(), e);
+                }
+                all_ok = false;
+            }
+        }
+    }
 
-    let url = format!("{}/{}", server_url, filename);
-    client.delete(url).send()
+    Ok(all_ok)
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let matches = Command::new("File Server Client")
-        .version("1.0")
-        .author("Vadim Smirnov <vadim@ntkernel.com>")
-        .about("Handles file operations with a server")
-        .arg(
-            Arg::new("generate")
-                .long("generate")
-                .short('g')
-                .value_name("FILE")
-                .help("Generates a file of specified size"),
-        )
-        .arg(
-            Arg::new("upload")
-                .long("upload")
-                .short('u')
-                .value_name("FILE")
-                .help("Uploads the specified file"),
-        )
-        .arg(
-            Arg::new("download")
-                .long("download")
-                .short('d')
-                .value_name("FILE")
-                .help("Downloads the specified file"),
-        )
-        .arg(
-            Arg::new("chunked")
-                .long("chunked")
-                .short('c')
-                .help("Enables chunked download")
-                .action(clap::ArgAction::SetTrue)
-                .default_value("false"),
-        ) // Set the action for this argument)
-        .arg(
-            Arg::new("server")
-                .long("server")
-                .short('s')
-                .value_name("URL")
-                .help("Sets the server URL")
-                .required(false),
+// Authorization header from --user or --token, falling back to the credentials of the config file
+fn authorization_header(
+    matches: &ArgMatches,
+    auth: &AuthConfig,
+) -> Result<Option<HeaderValue>, InvalidHeaderValue> {
+    let basic = |credentials: &str| {
+        format!(
+            "Basic {}",
+            base64::engine::general_purpose::STANDARD.encode(credentials)
         )
-        .arg(
-            Arg::new("size")
-                .long("size")
-                .value_name("SIZE")
-                .help("Sets the file size for generation"),
-        )
-        .arg(
-            Arg::new("timeout")
-                .long("timeout")
-                .short('t')
-                .value_name("TIMEOUT")
-                .help("Specifies the HTTP request timeout for upload")
-                .default_value("30"),
-        ) // Default to 1 iteration)
-        .arg(
-            Arg::new("iterations")
-                .long("iterations")
-                .short('i')
-                .value_name("NUMBER")
-                .help("Specifies the number of iterations for upload/download")
-                .default_value("1"),
-        ) // Default to 1 iteration)
-        .get_matches();
-
-    if !matches.args_present() {
-        println!("No arguments provided. Use --help for usage information.");
-        return Ok(());
-    }
+    };
+    let value = match (
+        matches.get_one::<String>("user"),
+        matches.get_one::<String>("token"),
+    ) {
+        (Some(user), _) if user.contains(':') => Some(basic(user)),
+        (Some(user), _) => Some(basic(&format!("{}:", user))),
+        (None, Some(token)) => Some(format!("Bearer {}", token)),
+        (None, None) => match (&auth.username, &auth.token) {
+            (Some(username), _) => Some(basic(&format!(
+                "{}:{}",
+                username,
+                auth.password.as_deref().unwrap_or("")
+            ))),
+            (None, Some(token)) => Some(format!("Bearer {}", token)),
+            (None, None) => None,
+        },
+    };
 
-    let server_url = matches.get_one::<String>("server");
+    value
+        .map(|value| {
+            // Keeps the credentials out of the verbose header log
+            let mut value = HeaderValue::from_str(&value)?;
+            value.set_sensitive(true);
+            Ok(value)
+        })
+        .transpose()
+}
 
-    let iterations = matches
-        .get_one::<String>("iterations")
-        .and_then(|it| it.parse::<usize>().ok())
-        .unwrap_or(1);
+// Command line flags take precedence over the config file, which takes precedence over the flag defaults
+fn client_from_matches(
+    matches: &ArgMatches,
+    config: &Config,
+) -> Result<HttpClient, Box<dyn std::error::Error>> {
+    let user_agent = match &config.user_agent {
+        Some(user_agent) if !is_explicit(matches, "user-agent") => user_agent,
+        _ => matches.get_one::<String>("user-agent").unwrap(),
+    };
+    let retries = match config.retries {
+        Some(retries) if !is_explicit(matches, "retries") => retries,
+        _ => *matches.get_one::<u32>("retries").unwrap(),
+    };
+    let ca_cert = matches
+        .get_one::<String>("cacert")
+        .map(Path::new)
+        .or(config.tls.ca_cert.as_deref());
+    let verify_certs = !matches.get_flag("insecure")
+        && (matches.get_flag("verify-certs")
+            || ca_cert.is_some()
+            || config.tls.verify == Some(true));
 
-    let timeout = matches
-        .get_one::<String>("timeout")
-        .and_then(|it| it.parse::<u64>().ok())
-        .unwrap_or(30);
-
-    if let Some(file) = matches.get_one::<String>("generate") {
-        let size = matches
-            .get_one::<String>("size")
-            .map(|s| s.parse().unwrap())
-            .unwrap_or(1024);
-        let path = Path::new(file);
-        match generate_random_text_file(path, size) {
-            Ok(hash) => println!("SHA256: {}", hash),
-            Err(e) => eprintln!("Error: {}", e),
+    let mut headers = HeaderMap::new();
+    if let Some(authorization) = authorization_header(matches, &config.auth)? {
+        headers.insert(AUTHORIZATION, authorization);
+    }
+    // Explicit -H headers replace the Authorization header built above
+    headers.extend(
+        matches
+            .get_many::<(HeaderName, HeaderValue)>("header")
+            .into_iter()
+            .flatten()
+            .cloned(),
+    );
+
+    // Binding to the unspecified address of a family restricts connections to that family
+    let local_address = if matches.get_flag("ipv4") {
+        Some(IpAddr::V4(Ipv4Addr::UNSPECIFIED))
+    } else if matches.get_flag("ipv6") {
+        Some(IpAddr::V6(Ipv6Addr::UNSPECIFIED))
+    } else {
+        if let Some(server) = matches
+            .get_one::<String>("server")
+            .or(config.server.as_ref())
+        {
+            log_resolved_addresses(server);
         }
+        None
+    };
+
+    // A cookie given on the command line or a jar file enables the cookie store as well
+    let manual_cookies: Vec<(String, String)> = matches
+        .get_many::<(String, String)>("cookie")
+        .into_iter()
+        .flatten()
+        .cloned()
+        .collect();
+    let cookie_jar = matches.get_one::<String>("cookie-jar").map(Path::new);
+    let cookies = if matches.get_flag("persist-cookies")
+        || !manual_cookies.is_empty()
+        || cookie_jar.is_some()
+    {
+        Some(CookieJar::new(manual_cookies, cookie_jar).map_err(|e| {
+            format!(
+                "Error reading cookie jar {}: {}",
+                cookie_jar.unwrap_or(Path::new("")).display(),
+                e
+            )
+        })?)
     } else {
-        let mut upload_durations = Vec::new();
-        let mut download_durations = Vec::new();
-
-        for _ in 0..iterations {
-            // Check if upload is specified
-            if let Some(file) = matches.get_one::<String>("upload") {
-                if server_url.is_none() {
-                    eprintln!(
-                        "{} - Server URL is required for uploading files.",
-                        Local::now()
-                    );
-                    std::process::exit(1);
-                }
-                let server = server_url.unwrap();
+        None
+    };
+
+    let connect_timeout = match config.connect_timeout {
+        Some(timeout) if !is_explicit(matches, "connect-timeout") => timeout,
+        _ => *matches.get_one::<u64>("connect-timeout").unwrap(),
+    };
+
+    HttpClient::new(ClientOptions {
+        proxy: matches
+            .get_one::<String>("proxy")
+            .or(config.proxy.as_ref())
+            .map(String::as_str),
+        connect_timeout: Duration::from_secs(connect_timeout),
+        request_timeout: Duration::from_secs(request_timeout(matches, config)),
+        user_agent,
+        headers,
+        // -vv also logs bodies
+        dump_body: matches.get_flag("dump-body") || matches.get_count("verbose") >= 2,
+        dump_headers: matches.get_flag("dump-headers"),
+        audit_log: matches.get_one::<String>("audit-log").map(String::as_str),
+        // 0 is unlimited, as for the per-direction rates
+        rate_limit: matches
+            .get_one::<u64>("limit-rate")
+            .copied()
+            .filter(|&rate| rate > 0),
+        upload_rate: matches.get_one::<u64>("upload-rate").copied(),
+        download_rate: matches.get_one::<u64>("download-rate").copied(),
+        retries,
+        verify_certs,
+        ca_cert,
+        max_redirects: if matches.get_flag("no-follow") {
+            Some(0)
+        } else {
+            matches.get_one::<usize>("max-redirects").copied()
+        },
+        show_progress: show_progress(matches),
+        ok_statuses: matches
+            .get_many::<u16>("ok-status")
+            .map(|statuses| statuses.copied().collect())
+            .unwrap_or_default(),
+        expected_statuses: matches
+            .get_many::<u16>("expect-status")
+            .map(|statuses| statuses.copied().collect())
+            .unwrap_or_default(),
+        local_address,
+        print_ip: matches.get_flag("print-ip"),
+        keepalive: !matches.get_flag("no-keepalive"),
+        cookies,
+        http_version: if matches.get_flag("http1") {
+            Some(HttpVersion::Http1)
+        } else if matches.get_flag("http2") {
+            Some(HttpVersion::Http2)
+        } else {
+            None
+        },
+        async_client: matches.get_flag("async"),
+    })
+}
 
-                // Attempt to delete the file from the server before uploading
-                let _ = delete_file(server, file);
+// Seconds a whole request may take, from connecting until its body has been transferred
+fn request_timeout(matches: &ArgMatches, config: &Config) -> u64 {
+    match config.timeout {
+        Some(timeout) if !is_explicit(matches, "timeout") => timeout,
+        _ => *matches.get_one::<u64>("timeout").unwrap(),
+    }
+}
 
-                // Proceed to upload the file
-                println!("{} - Start uploading file: {}", Local::now(), file);
+// Progress bars are drawn on stderr, but only make sense when someone watches the output
+fn show_progress(matches: &ArgMatches) -> bool {
+    matches.get_count("quiet") == 0
+        && !matches.get_flag("no-progress")
+        && io::stdout().is_terminal()
+}
 
-                // Record start time
-                let start_time = Instant::now();
+const RETRY_DELAY: Duration = Duration::from_secs(1);
 
-                match upload_file(server, Path::new(file), timeout) {
-                    Ok(response) => {
-                        // Calculate the duration and store it
-                        let duration = start_time.elapsed();
-                        upload_durations.push(duration);
-                        println!(
-                            "{} - {}: Uploaded. Status: {}\nTime taken: {:.2?} seconds",
-                            Local::now(),
-                            file,
-                            response.status(),
-                            duration
-                        );
-                    }
-                    Err(e) => eprintln!("{} - Error uploading file {}: {}", Local::now(), file, e),
-                }
+// Runs a transfer again after a failure, up to `retries` more times
+fn with_retries<T, E: std::fmt::Display>(
+    retries: u32,
+    mut transfer: impl FnMut() -> Result<T, E>,
+) -> Result<T, E> {
+    let mut attempt = 0;
+    loop {
+        match transfer() {
+            Err(e) if attempt < retries => {
+                attempt += 1;
+                tracing::warn!("{}, retrying ({}/{})", e, attempt, retries);
+                std::thread::sleep(RETRY_DELAY);
             }
+            result => return result,
+        }
+    }
+}
 
-            // Check if download is specified
-            if let Some(file) = matches.get_one::<String>("download") {
-                if server_url.is_none() {
-                    eprintln!(
-                        "{} - Server URL is required for downloading files.",
-                        Local::now()
-                    );
-                    std::process::exit(1);
-                }
+// How a file is sent to the server
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UploadMethod {
+    // multipart/form-data POST, to /upload by default
+    Post,
+    // The raw file bytes as the body of a PUT, to /<name> by default
+    Put,
+}
+
+// Settings for the upload request
+pub struct UploadOptions<'a> {
+    pub method: UploadMethod,
+    pub timeout_secs: u64,
+    // Multipart field name of the file part
+    pub field_name: &'a str,
+    // Filename sent in the Content-Disposition header instead of the local file name
+    pub remote_name: Option<&'a str>,
+    // Splits a single file into parts of this many bytes sent to the chunked upload endpoint
+    pub part_size: Option<u64>,
+    // MIME type of the file part or raw body instead of the one guessed from the file extension
+    pub content_type: Option<&'a str>,
+    // Stream the file through a gzip encoder and send it with Content-Encoding: gzip
+    pub gzip: bool,
+    // Endpoint path template instead of the default of the method, {name} is the remote file name
+    pub path: Option<&'a str>,
+    // Encode '/' in the remote file name as %2F instead of keeping it as a path separator
+    pub encode_slash: bool,
+    // Algorithm of the hash of the data sent, where it is computed
+    pub hash: HashAlgorithm,
+}
 
-                let chunked = matches.get_one::<bool>("chunked").copied().unwrap_or(false);
-                println!("{} - Start downloading file: {}", Local::now(), file);
+// Name of the file on the server, --remote-name or the local file name
+fn remote_file_name(filename: &Path, options: &UploadOptions) -> Result<String, &'static str> {
+    match options.remote_name {
+        Some(name) => Ok(name.to_string()),
+        None => filename
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .ok_or("the upload path has no file name"),
+    }
+}
 
-                // Record start time
-                let start_time = Instant::now();
+// Characters escaped in a path segment, as by the WHATWG URL standard plus '%' and '/' so that a
+// file name is sent verbatim
+const PATH_SEGMENT: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}')
+    .add(b'%')
+    .add(b'/');
 
-                match download_file(server_url.unwrap(), file, chunked) {
-                    Ok((size, hash)) => {
-                        // Calculate the duration and store it
-                        let duration = start_time.elapsed();
-                        download_durations.push(duration);
-                        println!(
-                            "{} - {}: Downloaded chunked = {} Size = {} bytes SHA256: {}\nTime taken: {:.2?} seconds",
-                            Local::now(),
-                            file,
-                            chunked,
-                            size,
-                            hash,
-                            duration
-                        );
-                    }
-                    Err(e) => {
-                        eprintln!("{} - Error downloading file {}: {}", Local::now(), file, e)
-                    }
-                }
-            }
+// Placeholders of endpoint path templates that are replaced by the file name
+const NAME_PLACEHOLDERS: [&str; 2] = ["{name}", "{file}"];
+
+fn has_name_placeholder(template: &str) -> bool {
+    NAME_PLACEHOLDERS
+        .iter()
+        .any(|placeholder| template.contains(placeholder))
+}
+
+// Percent-encodes a file name for a URL path. The '/' of a relative path in a recursive upload
+// separates segments and is kept, unless `encode_slash` makes it part of the name as %2F.
+fn encode_path(name: &str, encode_slash: bool) -> String {
+    if encode_slash {
+        return utf8_percent_encode(name, PATH_SEGMENT).to_string();
+    }
+    name.split('/')
+        .map(|segment| utf8_percent_encode(segment, PATH_SEGMENT).to_string())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+// Expands {name} or {file} in an endpoint path template to the URL below the server. A template
+// without a placeholder that ends in '/' is a directory the file name is appended to.
+// `encode_slash` is --encode-slash, see `encode_path`.
+fn endpoint_url(server_url: &str, template: &str, name: &str, encode_slash: bool) -> String {
+    let name = encode_path(name, encode_slash);
+    let mut path = template.trim_start_matches('/').to_string();
+    for placeholder in NAME_PLACEHOLDERS {
+        path = path.replace(placeholder, &name);
+    }
+    if path.ends_with('/') && !has_name_placeholder(template) {
+        path.push_str(&name);
+    }
+    format!("{}/{}", server_url.trim_end_matches('/'), path)
+}
+
+// URL an upload is sent to, the remote file name replaces {name} in the path template
+fn upload_url(server_url: &str, options: &UploadOptions, name: &str) -> String {
+    let template = options.path.unwrap_or(match options.method {
+        UploadMethod::Post => "/upload",
+        UploadMethod::Put => "/{name}",
+    });
+    endpoint_url(server_url, template, name, options.encode_slash)
+}
+
+// Applies --content-type to a multipart part, logged since the part headers are not part of the request headers
+fn with_content_type(part: Part, options: &UploadOptions) -> reqwest::Result<Part> {
+    match options.content_type {
+        Some(content_type) => {
+            tracing::debug!("> Part content-type: {}", content_type);
+            part.mime_str(content_type)
         }
+        None => Ok(part),
+    }
+}
+
+fn post_form(
+    client: &HttpClient,
+    url: String,
+    form: reqwest::blocking::multipart::Form,
+    size: Option<u64>,
+    timeout_secs: u64,
+) -> Result<reqwest::blocking::Response, Box<dyn std::error::Error>> {
+    let request = client
+        .post(url)
+        .timeout(Duration::from_secs(timeout_secs)) // Set the timeout to the specified number of seconds
+        .multipart(form);
+    let response = match size {
+        Some(size) => client.send_sized(request, size)?,
+        None => client.send(request)?,
+    };
+    Ok(response)
+}
+
+// Reader that hashes and counts the bytes passing through it, the state is shared since the
+// reader itself is consumed by the request body
+struct HashingReader<R> {
+    inner: R,
+    state: Arc<Mutex<(FileHasher, u64)>>,
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.0.update(&buf[..read]);
+        state.1 += read as u64;
+        Ok(read)
+    }
+}
+
+// Streams standard input as the file part without buffering it, the length is unknown so the body
+// is sent with chunked transfer encoding. Returns the response with the size and SHA256 of the data sent.
+fn upload_stdin(
+    client: &HttpClient,
+    server_url: &str,
+    options: &UploadOptions,
+) -> Result<(Response, u64, String), Box<dyn std::error::Error>> {
+    let state = Arc::new(Mutex::new((options.hash.hasher(), 0)));
+    let reader = HashingReader {
+        inner: io::stdin(),
+        state: Arc::clone(&state),
+    };
+    let bar = transfer_bar(None, client.show_progress());
+
+    let name = options.remote_name.unwrap_or("stdin");
+    let part = Part::reader(transfer_reader(client, reader, &bar)).file_name(name.to_string());
+    let part = with_content_type(part, options)?;
+    let form = reqwest::blocking::multipart::Form::new().part(options.field_name.to_string(), part);
+    let url = upload_url(server_url, options, name);
+    let result = post_form(client, url, form, None, options.timeout_secs);
+    finish_bar(&bar, &result);
+    let response = result?;
+
+    let state = state.lock().unwrap_or_else(|e| e.into_inner());
+    Ok((response, state.1, state.0.clone().finalize()))
+}
+
+// Wraps an upload body to throttle it and report its progress as needed
+fn transfer_reader<R: Read + Send + 'static>(
+    client: &HttpClient,
+    reader: R,
+    bar: &ProgressBar,
+) -> Box<dyn Read + Send> {
+    let reader: Box<dyn Read + Send> = match client.upload_rate() {
+        Some(rate) => Box::new(ThrottledReader::new(reader, rate)),
+        None => Box::new(reader),
+    };
+    if bar.is_hidden() {
+        reader
+    } else {
+        Box::new(ProgressReader::new(reader, bar.clone()))
+    }
+}
+
+// Multipart part streaming a file, throttled and tracked by the progress bar when enabled
+fn file_part(client: &HttpClient, filename: &Path, bar: &ProgressBar) -> io::Result<Part> {
+    if client.upload_rate().is_none() && bar.is_hidden() {
+        return Part::file(filename);
+    }
+    let file = File::open(filename)?;
+    let size = file.metadata()?.len();
+    let mut part = Part::reader_with_length(transfer_reader(client, file, bar), size);
+    if let Some(name) = filename.file_name() {
+        part = part.file_name(name.to_string_lossy().into_owned());
+    }
+    Ok(part)
+}
+
+fn upload_file(
+    client: &HttpClient,
+    server_url: &str,
+    filename: &Path,
+    options: &UploadOptions,
+) -> Result<reqwest::blocking::Response, Box<dyn std::error::Error>> {
+    if options.method == UploadMethod::Put {
+        return put_file(client, server_url, filename, options);
+    }
+    let size = filename.metadata()?.len();
+    let bar = transfer_bar(Some(size), client.show_progress());
+    let mut part = with_content_type(file_part(client, filename, &bar)?, options)?;
+    if let Some(remote_name) = options.remote_name {
+        part = part.file_name(remote_name.to_string());
+    }
+    let form = reqwest::blocking::multipart::Form::new().part(options.field_name.to_string(), part);
+    let url = upload_url(server_url, options, &remote_file_name(filename, options)?);
+    let result = post_form(client, url, form, Some(size), options.timeout_secs);
+    finish_bar(&bar, &result);
+    result
+}
+
+// Streams a file as the raw body of a PUT to /<name>, for servers that do not accept multipart forms
+fn put_file(
+    client: &HttpClient,
+    server_url: &str,
+    filename: &Path,
+    options: &UploadOptions,
+) -> Result<reqwest::blocking::Response, Box<dyn std::error::Error>> {
+    let name = remote_file_name(filename, options)?;
+    let file = File::open(filename)?;
+    let size = file.metadata()?.len();
+    let bar = transfer_bar(Some(size), client.show_progress());
+    let request = client
+        .put(upload_url(server_url, options, &name))
+        .timeout(Duration::from_secs(options.timeout_secs))
+        .header(
+            reqwest::header::CONTENT_TYPE,
+            options.content_type.unwrap_or("application/octet-stream"),
+        )
+        .body(reqwest::blocking::Body::sized(
+            transfer_reader(client, file, &bar),
+            size,
+        ));
+    let result = client.send_sized(request, size).map_err(Into::into);
+    finish_bar(&bar, &result);
+    result
+}
+
+// Reader that counts the bytes passing through it into a shared counter
+struct CountingReader<R> {
+    inner: R,
+    count: Arc<AtomicU64>,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.count.fetch_add(read as u64, Ordering::Relaxed);
+        Ok(read)
+    }
+}
+
+// Outcome of a gzip-compressed upload, the hash covers the uncompressed file
+struct CompressedUpload {
+    response: Response,
+    compressed_size: u64,
+    hash: String,
+}
+
+// Logs how well a file compresses before it is uploaded with --compress, which streams the
+// compressed data and so only knows its size afterwards. Compressed once into a sink for that.
+fn log_compression(path: &Path) {
+    let sizes = File::open(path).and_then(|file| {
+        let original = file.metadata()?.len();
+        let mut encoder = flate2::read::GzEncoder::new(file, flate2::Compression::default());
+        let compressed = io::copy(&mut encoder, &mut io::sink())?;
+        Ok((original, compressed))
+    });
+    match sizes {
+        Ok((original, compressed)) => tracing::info!(
+            "{}: Compressed: {} → {} ({:.1}% reduction)",
+            path.display(),
+            human_size(original),
+            human_size(compressed),
+            100.0 - compressed as f64 * 100.0 / original.max(1) as f64
+        ),
+        Err(e) => tracing::warn!("{}: Unable to compress: {}", path.display(), e),
+    }
+}
+
+// Streams a file through a gzip encoder, as the multipart file part or as the raw PUT body, marked
+// with Content-Encoding: gzip. The compressed length is unknown up front so the body is sent chunked.
+fn upload_compressed(
+    client: &HttpClient,
+    server_url: &str,
+    filename: &Path,
+    options: &UploadOptions,
+) -> Result<CompressedUpload, Box<dyn std::error::Error>> {
+    let name = remote_file_name(filename, options)?;
+    let file = File::open(filename)?;
+    // The bar follows the uncompressed file, whose size is known
+    let bar = transfer_bar(Some(file.metadata()?.len()), client.show_progress());
+    let state = Arc::new(Mutex::new((options.hash.hasher(), 0)));
+    let source = HashingReader {
+        inner: ProgressReader::new(file, bar.clone()),
+        state: Arc::clone(&state),
+    };
+    let compressed_size = Arc::new(AtomicU64::new(0));
+    let encoder = CountingReader {
+        inner: flate2::read::GzEncoder::new(source, flate2::Compression::default()),
+        count: Arc::clone(&compressed_size),
+    };
+    // Throttling applies to the bytes on the wire
+    let body = transfer_reader(client, encoder, &ProgressBar::hidden());
 
-        // Calculate and print the average times
-        if upload_durations.len() > 0 {
-            let average_upload =
-                upload_durations.iter().copied().sum::<Duration>() / upload_durations.len() as u32;
-            println!(
-                "{} - Average upload time: {:.2?} seconds",
-                Local::now(),
-                average_upload
+    let result = match options.method {
+        UploadMethod::Put => {
+            let request = client
+                .put(upload_url(server_url, options, &name))
+                .timeout(Duration::from_secs(options.timeout_secs))
+                .header(
+                    reqwest::header::CONTENT_TYPE,
+                    options.content_type.unwrap_or("application/octet-stream"),
+                )
+                .header(reqwest::header::CONTENT_ENCODING, "gzip")
+                .body(reqwest::blocking::Body::new(body));
+            client.send(request).map_err(Into::into)
+        }
+        UploadMethod::Post => {
+            let mut headers = HeaderMap::new();
+            headers.insert(
+                reqwest::header::CONTENT_ENCODING,
+                HeaderValue::from_static("gzip"),
             );
+            let url = upload_url(server_url, options, &name);
+            let part = Part::reader(body).file_name(name).headers(headers);
+            let part = with_content_type(part, options)?;
+            let form = reqwest::blocking::multipart::Form::new()
+                .part(options.field_name.to_string(), part);
+            post_form(client, url, form, None, options.timeout_secs)
         }
+    };
+    finish_bar(&bar, &result);
+    let response = result?;
 
-        if download_durations.len() > 0 {
-            let average_download = download_durations.iter().copied().sum::<Duration>()
-                / download_durations.len() as u32;
+    let state = state.lock().unwrap_or_else(|e| e.into_inner());
+    Ok(CompressedUpload {
+        response,
+        compressed_size: compressed_size.load(Ordering::Relaxed),
+        hash: state.0.clone().finalize(),
+    })
+}
 
-            println!(
-                "{} - Average download time: {:.2?} seconds",
-                Local::now(),
-                average_download
-            );
+// Endpoint of the parts of --upload-chunk-size uploads
+const CHUNKED_UPLOAD_PATH: &str = "/upload-chunked/{name}";
+
+// Uploads a file as parts of `part_size` bytes to /upload-chunked/<name>?part=<index>&total=<count>,
+// retrying each part on its own, then posts the size and SHA256 of the whole file to
+// /upload-chunked/<name>/complete so the server can verify the assembled file
+fn upload_file_in_parts(
+    client: &HttpClient,
+    server_url: &str,
+    filename: &Path,
+    part_size: u64,
+    options: &UploadOptions,
+) -> Result<reqwest::blocking::Response, Box<dyn std::error::Error>> {
+    let name = remote_file_name(filename, options)?;
+    let url = endpoint_url(server_url, CHUNKED_UPLOAD_PATH, &name, options.encode_slash);
+    let mut file = File::open(filename)?;
+    let size = file.metadata()?.len();
+    // An empty file is still sent as a single empty part
+    let total = size.div_ceil(part_size).max(1);
+
+    let bar = transfer_bar(Some(size), client.show_progress());
+    let result = (|| -> Result<reqwest::blocking::Response, Box<dyn std::error::Error>> {
+        // The server verifies the assembled file with SHA256, whatever --hash-algorithm is
+        let mut hasher = Sha256::default();
+        let mut buffer = Vec::new();
+        for index in 0..total {
+            let offset = index * part_size;
+            buffer.clear();
+            (&mut file).take(part_size).read_to_end(&mut buffer)?;
+            hasher.update(&buffer);
+
+            with_retries(client.retries(), || {
+                // A retried part starts over on the bar
+                bar.set_position(offset);
+                let body = reqwest::blocking::Body::sized(
+                    transfer_reader(client, io::Cursor::new(buffer.clone()), &bar),
+                    buffer.len() as u64,
+                );
+                let request = client
+                    .post(&url)
+                    .query(&[("part", index), ("total", total)])
+                    .timeout(Duration::from_secs(options.timeout_secs))
+                    .header(
+                        reqwest::header::CONTENT_TYPE,
+                        options.content_type.unwrap_or("application/octet-stream"),
+                    )
+                    .body(body);
+                check_status(client, client.send_sized(request, buffer.len() as u64)?)
+            })?;
+            tracing::debug!("{}: Sent part {} of {}", name, index + 1, total);
         }
-    }
 
-    Ok(())
+        let request = client
+            .post(format!("{}/complete", url))
+            .timeout(Duration::from_secs(options.timeout_secs))
+            .json(&serde_json::json!({
+                "total": total,
+                "size": size,
+                "sha256": hasher.finalize(),
+            }));
+        Ok(with_retries(client.retries(), || {
+            client.send(request.try_clone().unwrap())
+        })?)
+    })();
+    finish_bar(&bar, &result);
+    result
+}
+
+// Uploads several files in a single multipart request, one part per file named <field>0, <field>1, ...
+fn upload_files(
+    client: &HttpClient,
+    server_url: &str,
+    filenames: &[&Path],
+    options: &UploadOptions,
+) -> Result<reqwest::blocking::Response, Box<dyn std::error::Error>> {
+    let mut size = 0;
+    for filename in filenames {
+        size += filename.metadata()?.len();
+    }
+    // One bar for the whole request, the parts are sent one after another
+    let bar = transfer_bar(Some(size), client.show_progress());
+    let mut form = reqwest::blocking::multipart::Form::new();
+    for (index, filename) in filenames.iter().enumerate() {
+        form = form.part(
+            format!("{}{}", options.field_name, index),
+            with_content_type(file_part(client, filename, &bar)?, options)?,
+        );
+    }
+    // A {name} in the path template is refused for several files up front
+    let url = upload_url(server_url, options, "");
+    let result = post_form(client, url, form, Some(size), options.timeout_secs);
+    finish_bar(&bar, &result);
+    result
+}
+
+// Collects the regular files below `dir` along with their '/'-separated paths relative to it, sorted
+// by name. Symlinks are skipped unless `follow_symlinks` is set, a directory is never entered twice.
+fn collect_upload_files(dir: &Path, follow_symlinks: bool) -> io::Result<Vec<(PathBuf, String)>> {
+    let mut files = Vec::new();
+    walk_upload_dir(dir, "", follow_symlinks, &mut HashSet::new(), &mut files)?;
+    Ok(files)
+}
+
+fn walk_upload_dir(
+    dir: &Path,
+    prefix: &str,
+    follow_symlinks: bool,
+    visited: &mut HashSet<PathBuf>,
+    files: &mut Vec<(PathBuf, String)>,
+) -> io::Result<()> {
+    // Guards against symlink loops
+    if !visited.insert(dir.canonicalize()?) {
+        return Ok(());
+    }
+
+    let mut entries = std::fs::read_dir(dir)?.collect::<io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+
+    for entry in entries {
+        let path = entry.path();
+        let name = format!("{}{}", prefix, entry.file_name().to_string_lossy());
+        let mut file_type = entry.file_type()?;
+        if file_type.is_symlink() {
+            if !follow_symlinks {
+                tracing::debug!("Skipping symlink: {}", path.display());
+                continue;
+            }
+            file_type = match std::fs::metadata(&path) {
+                Ok(metadata) => metadata.file_type(),
+                Err(e) => {
+                    tracing::warn!("Skipping broken symlink {}: {}", path.display(), e);
+                    continue;
+                }
+            };
+        }
+
+        if file_type.is_dir() {
+            walk_upload_dir(
+                &path,
+                &format!("{}/", name),
+                follow_symlinks,
+                visited,
+                files,
+            )?;
+        } else if file_type.is_file() {
+            files.push((path, name));
+        }
+    }
+    Ok(())
+}
+
+// Turns a server-relative filename into a relative path that cannot escape the target directory
+fn sanitize_relative_path(filename: &str) -> io::Result<PathBuf> {
+    let mut path = PathBuf::new();
+
+    for component in filename.split(['/', '\\']) {
+        match component {
+            "" | "." => {}
+            ".." => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Refusing path traversal in filename: {}", filename),
+                ))
+            }
+            _ => match Path::new(component).components().next() {
+                Some(Component::Normal(_)) => path.push(component),
+                // Drive prefixes like "C:" must not make the path absolute
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("Invalid component in filename: {}", filename),
+                    ))
+                }
+            },
+        }
+    }
+
+    if path.as_os_str().is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Empty filename: {}", filename),
+        ));
+    }
+    Ok(path)
+}
+
+// An output ending with a separator or naming an existing directory receives the server-relative filename
+fn resolve_output_path(output: &str, filename: &str) -> io::Result<PathBuf> {
+    let output_path = Path::new(output);
+    if output.ends_with(['/', '\\']) || output_path.is_dir() {
+        Ok(output_path.join(sanitize_relative_path(filename)?))
+    } else {
+        Ok(output_path.to_path_buf())
+    }
+}
+
+// Output path that streams a download to stdout
+const STDOUT_PATH: &str = "-";
+
+// True when downloaded data goes to stdout, which must then be kept free of log output
+fn stdout_is_data(matches: &ArgMatches) -> bool {
+    match matches.subcommand() {
+        Some(("download" | "roundtrip", sub)) => sub
+            .get_one::<String>("output")
+            .is_some_and(|output| output == STDOUT_PATH),
+        _ => false,
+    }
+}
+
+// Settings for a single download
+pub struct DownloadOptions<'a> {
+    // Endpoint path template, {name} is replaced by the file name
+    pub path: &'a str,
+    // Encode '/' in the file name as %2F instead of keeping it as a path separator
+    pub encode_slash: bool,
+    // Where the body is saved, `STDOUT_PATH` streams it to stdout
+    pub output: Option<&'a Path>,
+    // Continue a partially downloaded output file with a Range request
+    pub resume: bool,
+    // Number of byte ranges fetched concurrently into the output file, 1 downloads a single stream
+    pub parallel_chunks: usize,
+    // Minimum speed in bytes per second and how long it may be undercut before the download is aborted
+    pub min_speed: Option<(u64, Duration)>,
+    // Accept-Encoding to request, "none" sends no header. When set, encoded responses are decoded
+    // here so the hash covers the decoded data while the bytes on the wire are counted separately.
+    pub accept_encoding: Option<&'a str>,
+    pub hash: HashAlgorithm,
+    // Revalidates files cached by an earlier download instead of transferring them again
+    pub cache: Option<&'a DownloadCache>,
+}
+
+// Outcome of a download, `size` includes the `resumed` bytes that were already on disk
+pub struct Downloaded {
+    pub size: u64,
+    pub resumed: u64,
+    // Bytes received on the wire, smaller than the transferred size for compressed responses
+    pub wire_size: u64,
+    pub hash: String,
+    pub status: reqwest::StatusCode,
+    // Time to the response headers and reading the body, for a single stream only
+    pub phases: PhaseTimings,
+    // Served from the --cache-dir after the server answered 304 Not Modified
+    pub cached: bool,
+}
+
+// Downloads `url` as `chunks` byte ranges fetched concurrently and written at their offsets of the
+// pre-allocated output file. Returns None without touching the file if the server ignores ranges.
+fn download_parallel(
+    client: &HttpClient,
+    url: &str,
+    output: &Path,
+    options: &DownloadOptions,
+) -> Result<Option<Downloaded>, DownloadError> {
+    // A one-byte probe tells whether ranges are supported and how large the file is
+    let probe = client.send(client.get(url).header(reqwest::header::RANGE, "bytes=0-0"))?;
+    let size = match (probe.status(), content_range(probe.headers())) {
+        (reqwest::StatusCode::PARTIAL_CONTENT, (Some(0), Some(size))) => size,
+        _ => return Ok(None),
+    };
+    drop(probe);
+
+    if let Some(parent) = output.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    File::create(output)?.set_len(size)?;
+
+    let chunk_size = size.div_ceil(options.parallel_chunks as u64).max(1);
+    let ranges: Vec<(u64, u64)> = (0..size)
+        .step_by(chunk_size as usize)
+        .map(|start| (start, (start + chunk_size).min(size) - 1))
+        .collect();
+    // The rate limit and minimum speed apply to the whole download, every range gets its share
+    let share = |rate: u64| (rate / ranges.len().max(1) as u64).max(1);
+    let rate_limit = client.download_rate().map(share);
+    let min_speed = options
+        .min_speed
+        .map(|(speed, timeout)| (share(speed), timeout));
+
+    let bar = transfer_bar(Some(size), client.show_progress());
+    let results: Vec<Result<(), DownloadError>> = std::thread::scope(|scope| {
+        let workers: Vec<_> = ranges
+            .iter()
+            .enumerate()
+            .map(|(index, &(start, end))| {
+                let bar = bar.clone();
+                scope.spawn(move || {
+                    let start_time = Instant::now();
+                    download_range(
+                        client,
+                        url,
+                        output,
+                        (start, end),
+                        (rate_limit, min_speed),
+                        bar,
+                    )?;
+                    tracing::debug!(
+                        "Chunk {} (bytes {}-{}, {}) took {:.2?}",
+                        index,
+                        start,
+                        end,
+                        human_size(end - start + 1),
+                        start_time.elapsed()
+                    );
+                    Ok(())
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .map(|worker| worker.join().expect("download worker panicked"))
+            .collect()
+    });
+    let result = results.into_iter().collect::<Result<(), _>>();
+    finish_bar(&bar, &result);
+    result?;
+
+    Ok(Some(Downloaded {
+        size,
+        resumed: 0,
+        wire_size: size,
+        hash: hash_file(output, options.hash)?,
+        status: reqwest::StatusCode::PARTIAL_CONTENT,
+        phases: PhaseTimings::default(),
+        cached: false,
+    }))
+}
+
+// Fetches bytes start..=end of `url` and writes them at the same offset of the output file
+fn download_range(
+    client: &HttpClient,
+    url: &str,
+    output: &Path,
+    (start, end): (u64, u64),
+    (rate_limit, min_speed): (Option<u64>, Option<(u64, Duration)>),
+    bar: ProgressBar,
+) -> Result<(), DownloadError> {
+    let response = client.send(
+        client
+            .get(url)
+            .header(reqwest::header::RANGE, format!("bytes={}-{}", start, end)),
+    )?;
+    let response = check_status(client, response)?;
+    if response.status() != reqwest::StatusCode::PARTIAL_CONTENT
+        || content_range(response.headers()).0 != Some(start)
+    {
+        return Err(DownloadError::Status(response.status().as_u16()));
+    }
+    let reader: Box<dyn Read> = match rate_limit {
+        Some(rate) => Box::new(ThrottledReader::new(response, rate)),
+        None => Box::new(response),
+    };
+    let mut reader = ProgressReader::new(reader, bar).take(end - start + 1);
+
+    let mut file = std::fs::OpenOptions::new().write(true).open(output)?;
+    file.seek(io::SeekFrom::Start(start))?;
+    let mut monitor = speed_monitor(min_speed);
+    let mut buffer = vec![0u8; 64 * 1024];
+    let mut written = 0;
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        file.write_all(&buffer[..read])?;
+        written += read as u64;
+        check_speed(&mut monitor, read)?;
+    }
+    if written != end - start + 1 {
+        return Err(DownloadError::Io(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            format!("range {}-{} ended after {} bytes", start, end, written),
+        )));
+    }
+    Ok(())
+}
+
+fn speed_monitor(min_speed: Option<(u64, Duration)>) -> Option<(SpeedMonitor, u64)> {
+    min_speed.map(|(speed, timeout)| (SpeedMonitor::new(speed, timeout), speed))
+}
+
+// Aborts a download that stayed below --min-speed for longer than --min-speed-timeout
+fn check_speed(
+    monitor: &mut Option<(SpeedMonitor, u64)>,
+    read: usize,
+) -> Result<(), DownloadError> {
+    match monitor {
+        Some((monitor, required_bps)) => {
+            monitor
+                .record(read as u64)
+                .map_err(|measured_bps| DownloadError::SpeedTooLow {
+                    measured_bps,
+                    required_bps: *required_bps,
+                })
+        }
+        None => Ok(()),
+    }
+}
+
+// Start offset and total length of a "bytes <start>-<end>/<total>" or "bytes */<total>" Content-Range
+fn content_range(headers: &HeaderMap) -> (Option<u64>, Option<u64>) {
+    let Some(range) = headers
+        .get(reqwest::header::CONTENT_RANGE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("bytes "))
+    else {
+        return (None, None);
+    };
+    let (span, total) = range.split_once('/').unwrap_or((range, "*"));
+    let start = span
+        .split_once('-')
+        .and_then(|(start, _)| start.parse().ok());
+    (start, total.parse().ok())
+}
+
+fn download_file(
+    client: &HttpClient,
+    server_url: &str,
+    filename: &str,
+    options: &DownloadOptions,
+) -> Result<Downloaded, DownloadError> {
+    let url = endpoint_url(server_url, options.path, filename, options.encode_slash);
+    let output = options.output;
+
+    if let Some(path) =
+        output.filter(|path| options.parallel_chunks > 1 && *path != Path::new(STDOUT_PATH))
+    {
+        match download_parallel(client, &url, path, options)? {
+            Some(downloaded) => return Ok(downloaded),
+            None => tracing::warn!(
+                "{}: Server does not support range requests, downloading a single stream",
+                filename
+            ),
+        }
+    }
+
+    let partial = match output {
+        Some(path) if options.resume && path != Path::new(STDOUT_PATH) => {
+            path.metadata().map_or(0, |m| m.len())
+        }
+        _ => 0,
+    };
+    let mut request = client.get(&url);
+    if partial > 0 {
+        request = request.header(reqwest::header::RANGE, format!("bytes={}-", partial));
+    }
+    // The ETag is the stronger validator, the date is only sent for servers without ETags
+    let cached = options.cache.and_then(|cache| cache.lookup(filename));
+    if let Some(entry) = &cached {
+        if let Some(etag) = &entry.etag {
+            request = request.header(reqwest::header::IF_NONE_MATCH, etag);
+        } else if let Some(last_modified) = &entry.last_modified {
+            request = request.header(reqwest::header::IF_MODIFIED_SINCE, last_modified);
+        }
+    }
+    if let Some(encoding) = options
+        .accept_encoding
+        .filter(|&encoding| encoding != "none")
+    {
+        request = request.header(reqwest::header::ACCEPT_ENCODING, encoding);
+    }
+    let mut response = client.send(request)?;
+
+    let resumed = match (response.status(), content_range(response.headers())) {
+        _ if partial == 0 => 0,
+        (reqwest::StatusCode::PARTIAL_CONTENT, (Some(start), _)) if start == partial => partial,
+        (reqwest::StatusCode::RANGE_NOT_SATISFIABLE, (_, Some(total))) if total == partial => {
+            tracing::info!("{}: Already complete, nothing to resume", filename);
+            let path = output.unwrap();
+            return Ok(Downloaded {
+                size: partial,
+                resumed: partial,
+                wire_size: 0,
+                hash: hash_file(path, options.hash)?,
+                status: reqwest::StatusCode::RANGE_NOT_SATISFIABLE,
+                phases: PhaseTimings::default(),
+                cached: false,
+            });
+        }
+        (status, _) => {
+            tracing::warn!(
+                "{}: Unable to resume ({}), downloading the whole file",
+                filename,
+                status
+            );
+            if status != reqwest::StatusCode::OK {
+                response = client.send(client.get(&url))?;
+            }
+            0
+        }
+    };
+
+    if let (reqwest::StatusCode::NOT_MODIFIED, Some(cache), Some(entry)) =
+        (response.status(), options.cache, &cached)
+    {
+        let ttfb = http::response_wait(&response);
+        return serve_from_cache(cache, entry, output, ttfb);
+    }
+
+    // An error page is reported instead of being saved or piped as the file
+    let response = check_status(client, response)?;
+    let ttfb = http::response_wait(&response);
+    let content_length = response.content_length();
+    let status = response.status();
+    let content_encoding = response
+        .headers()
+        .get(reqwest::header::CONTENT_ENCODING)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_ascii_lowercase)
+        .filter(|_| options.accept_encoding.is_some());
+    // Only complete files the server can revalidate are cached
+    let header = |name| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|value: &HeaderValue| value.to_str().ok())
+            .map(str::to_string)
+    };
+    let etag = header(reqwest::header::ETAG);
+    let last_modified = header(reqwest::header::LAST_MODIFIED);
+    let mut cache_file = match options.cache {
+        Some(cache)
+            if status == reqwest::StatusCode::OK && (etag.is_some() || last_modified.is_some()) =>
+        {
+            Some(cache.temp_file()?)
+        }
+        _ => None,
+    };
+    let wire_size = Arc::new(AtomicU64::new(0));
+    let received = CountingReader {
+        inner: response,
+        count: Arc::clone(&wire_size),
+    };
+    let received: Box<dyn Read> = match client.download_rate() {
+        Some(rate) => Box::new(ThrottledReader::new(received, rate)),
+        None => Box::new(received),
+    };
+    let decoded = matches!(
+        content_encoding.as_deref(),
+        Some("gzip" | "x-gzip" | "deflate" | "br")
+    );
+    if let Some(encoding) = content_encoding.as_deref() {
+        tracing::debug!("{}: Content-Encoding: {}", filename, encoding);
+    }
+    let mut reader: Box<dyn Read> = match content_encoding.as_deref() {
+        Some("gzip" | "x-gzip") => Box::new(flate2::read::GzDecoder::new(received)),
+        Some("deflate") => Box::new(flate2::read::ZlibDecoder::new(received)),
+        Some("br") => Box::new(brotli_decompressor::Decompressor::new(received, 64 * 1024)),
+        _ => received,
+    };
+
+    let mut writer: Option<Box<dyn Write>> = match output {
+        Some(path) if path == Path::new(STDOUT_PATH) => {
+            Some(Box::new(BufWriter::new(io::stdout().lock())))
+        }
+        Some(path) if resumed > 0 => Some(Box::new(BufWriter::new(
+            std::fs::OpenOptions::new().append(true).open(path)?,
+        ))),
+        Some(path) => {
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            Some(Box::new(BufWriter::new(File::create(path)?)))
+        }
+        None => None,
+    };
+
+    // The hash covers the complete file, starting with the part that is already on disk
+    let mut hasher = options.hash.hasher();
+    if let Some(path) = output.filter(|_| resumed > 0 && options.hash != HashAlgorithm::None) {
+        hash_file_into(&mut hasher, path)?;
+    }
+    let mut buffer = vec![0u8; 64 * 1024];
+    let mut size = resumed;
+    let mut dumped = Vec::new();
+
+    // No bar while the data itself goes to the terminal, a spinner if the length is unknown
+    let to_stdout = output == Some(Path::new(STDOUT_PATH));
+    let bar = transfer_bar(
+        content_length.filter(|_| !decoded).map(|len| len + resumed),
+        client.show_progress() && !to_stdout,
+    );
+    bar.set_position(resumed);
+
+    let mut monitor = speed_monitor(options.min_speed);
+    let transfer_start = Instant::now();
+    let result = (|| -> Result<(), DownloadError> {
+        loop {
+            let read = reader.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            check_speed(&mut monitor, read)?;
+            hasher.update(&buffer[..read]);
+            if let Some(writer) = writer.as_mut() {
+                writer.write_all(&buffer[..read])?;
+            }
+            if let Some((writer, _)) = cache_file.as_mut() {
+                writer.write_all(&buffer[..read])?;
+            }
+            if client.dump_body_enabled() {
+                // Only what gets logged is kept
+                let room = BODY_LOG_LIMIT.saturating_sub(dumped.len());
+                dumped.extend_from_slice(&buffer[..read.min(room)]);
+            }
+            size += read as u64;
+            bar.set_position(size);
+        }
+        if let Some(writer) = writer.as_mut() {
+            writer.flush()?;
+        }
+        if let Some((writer, _)) = cache_file.as_mut() {
+            writer.flush()?;
+        }
+        Ok(())
+    })();
+    finish_bar(&bar, &result);
+    let cache_file = cache_file.map(|(writer, path)| {
+        drop(writer);
+        path
+    });
+    if let Err(e) = result {
+        if let Some(path) = cache_file {
+            let _ = std::fs::remove_file(path);
+        }
+        return Err(e);
+    }
+    let transfer = transfer_start.elapsed();
+    client.dump_body(&dumped, size - resumed);
+
+    let hash = hasher.finalize();
+    if let (Some(cache), Some(path)) = (options.cache, cache_file) {
+        // A failing cache does not fail the download
+        if let Err(e) = cache.store(filename, &path, etag, last_modified, &hash, size) {
+            tracing::warn!("{}: Unable to cache the download: {}", filename, e);
+            let _ = std::fs::remove_file(path);
+        }
+    }
+    Ok(Downloaded {
+        size,
+        resumed,
+        wire_size: wire_size.load(Ordering::Relaxed),
+        hash,
+        status,
+        phases: PhaseTimings {
+            ttfb,
+            transfer: Some(transfer),
+            ..PhaseTimings::default()
+        },
+        cached: false,
+    })
+}
+
+// Copies a file the server reported as not modified from the cache to the output
+fn serve_from_cache(
+    cache: &DownloadCache,
+    entry: &CacheEntry,
+    output: Option<&Path>,
+    ttfb: Option<Duration>,
+) -> Result<Downloaded, DownloadError> {
+    let start_time = Instant::now();
+    let object = cache.object_path(entry);
+    match output {
+        Some(path) if path == Path::new(STDOUT_PATH) => {
+            let mut stdout = io::stdout().lock();
+            io::copy(&mut File::open(&object)?, &mut stdout)?;
+            stdout.flush()?;
+        }
+        Some(path) => {
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::copy(&object, path)?;
+        }
+        None => {}
+    }
+    Ok(Downloaded {
+        size: entry.size,
+        resumed: 0,
+        wire_size: 0,
+        hash: entry.hash.clone(),
+        status: reqwest::StatusCode::NOT_MODIFIED,
+        phases: PhaseTimings {
+            ttfb,
+            transfer: Some(start_time.elapsed()),
+            ..PhaseTimings::default()
+        },
+        cached: true,
+    })
+}
+
+fn delete_file(
+    client: &HttpClient,
+    server_url: &str,
+    path: &str,
+    filename: &str,
+    encode_slash: bool,
+) -> reqwest::Result<Response> {
+    client.send(client.delete(endpoint_url(server_url, path, filename, encode_slash)))
+}
+
+// Removes a previous copy before an upload. A missing file is expected, any other failure is
+// logged since the upload may then fail or not replace the file.
+fn delete_before_upload(
+    client: &HttpClient,
+    server_url: &str,
+    path: &str,
+    filename: &str,
+    encode_slash: bool,
+) {
+    match delete_file(client, server_url, path, filename, encode_slash) {
+        Ok(response)
+            if response.status().is_success()
+                || response.status() == reqwest::StatusCode::NOT_FOUND => {}
+        Ok(response) => tracing::warn!(
+            "{}: Delete before upload failed: {}",
+            filename,
+            status_error(response)
+        ),
+        Err(e) => tracing::warn!("{}: Delete before upload failed: {}", filename, e),
+    }
+}
+
+// URL of the HEAD requests of the existence and size checks, from --head-path
+fn head_url(server_url: &str, filename: &str, matches: &ArgMatches) -> String {
+    endpoint_url(
+        server_url,
+        matches.get_one::<String>("head-path").unwrap(),
+        filename,
+        matches.get_flag("encode-slash"),
+    )
+}
+
+fn file_exists_on_server(client: &HttpClient, url: &str) -> Result<bool, DownloadError> {
+    let response = client.send(client.head(url))?;
+
+    match response.status() {
+        reqwest::StatusCode::OK => Ok(true),
+        reqwest::StatusCode::NOT_FOUND => Ok(false),
+        status => Err(DownloadError::Status(status.as_u16())),
+    }
+}
+
+// Condition of --upload-if-absent or --upload-if-changed for uploading a file at all
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum UploadCondition<'a> {
+    // --head-path and --encode-slash of the existence check
+    IfAbsent(&'a str, bool),
+    // The listing endpoint the server reports file hashes at
+    IfChanged(&'a str),
+}
+
+impl<'a> UploadCondition<'a> {
+    fn from_matches(matches: &'a ArgMatches) -> Option<Self> {
+        let flag = |id| {
+            matches
+                .try_get_one::<bool>(id)
+                .is_ok_and(|set| set == Some(&true))
+        };
+        if flag("upload-if-absent") {
+            Some(UploadCondition::IfAbsent(
+                matches.get_one::<String>("head-path").unwrap(),
+                matches.get_flag("encode-slash"),
+            ))
+        } else if flag("upload-if-changed") {
+            let endpoint = matches
+                .try_get_one::<String>("list-endpoint")
+                .ok()
+                .flatten()
+                .map_or("files", String::as_str);
+            Some(UploadCondition::IfChanged(endpoint))
+        } else {
+            None
+        }
+    }
+
+    // Why the upload of `path` as `remote_name` is not needed, None if it is. The listing of
+    // --upload-if-changed is fetched once into `listing`. A failed check is logged and uploads.
+    fn skip_reason(
+        self,
+        client: &HttpClient,
+        server: &str,
+        path: &Path,
+        remote_name: &str,
+        listing: &mut Option<Vec<ServerFile>>,
+    ) -> Option<&'static str> {
+        match self {
+            UploadCondition::IfAbsent(head_path, encode_slash) => {
+                let url = endpoint_url(server, head_path, remote_name, encode_slash);
+                match file_exists_on_server(client, &url) {
+                    Ok(exists) => exists.then_some("already on the server"),
+                    Err(e) => {
+                        tracing::warn!("{}: Unable to check for the file: {}", remote_name, e);
+                        None
+                    }
+                }
+            }
+            UploadCondition::IfChanged(endpoint) => {
+                if listing.is_none() {
+                    match list_files(client, server, endpoint) {
+                        Ok(files) => *listing = Some(files),
+                        Err(e) => {
+                            tracing::warn!(
+                                "{}: Unable to list the server files: {}",
+                                remote_name,
+                                e
+                            );
+                            return None;
+                        }
+                    }
+                }
+                let remote_hash = listing
+                    .as_ref()?
+                    .iter()
+                    .find(|file| file.name == remote_name)?
+                    .sha256
+                    .as_deref()?;
+                // The server reports SHA256 whatever --hash-algorithm is
+                match hash_file(path, HashAlgorithm::Sha256) {
+                    Ok(hash) => hash
+                        .eq_ignore_ascii_case(remote_hash)
+                        .then_some("unchanged on the server"),
+                    Err(e) => {
+                        tracing::warn!("{}: Unable to hash {}: {}", remote_name, path.display(), e);
+                        None
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn remote_file_size(client: &HttpClient, url: &str) -> Result<u64, DownloadError> {
+    let response = client.send(client.head(url))?;
+
+    if !response.status().is_success() {
+        return Err(DownloadError::Status(response.status().as_u16()));
+    }
+
+    // HEAD responses carry no body, so the header has to be parsed directly
+    response
+        .headers()
+        .get(reqwest::header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok())
+        .ok_or(DownloadError::ContentLength)
+}
+
+fn list_files(
+    client: &HttpClient,
+    server_url: &str,
+    endpoint: &str,
+) -> Result<Vec<ServerFile>, DownloadError> {
+    // The listing URL has no file name to encode
+    let url = endpoint_url(server_url, endpoint, "", false);
+    let files = check_status(client, client.send(client.get(url))?)?.json()?;
+    Ok(files)
+}
+
+fn print_file_table(files: &[ServerFile]) {
+    let name_width = files.iter().map(|f| f.name.len()).max().unwrap_or(0).max(4);
+    println!(
+        "{:<name_width$}  {:>12}  {:<64}  CREATED",
+        "NAME", "SIZE", "SHA256"
+    );
+    for file in files {
+        println!(
+            "{:<name_width$}  {:>12}  {:<64}  {}",
+            file.name,
+            human_size(file.size),
+            file.sha256.as_deref().unwrap_or("-"),
+            file.created_at.as_deref().unwrap_or("-")
+        );
+    }
+}
+
+fn require_server<'a>(server_url: Option<&'a String>, action: &str) -> &'a str {
+    match server_url {
+        Some(server) => server,
+        None => {
+            tracing::error!("Server URL is required for {}.", action);
+            std::process::exit(1);
+        }
+    }
+}
+
+// Exits the process unless every entry of the checksum file matches
+fn verify_checksums(checksum_file: &str, algorithm: HashAlgorithm, verbosity: Verbosity) {
+    if algorithm == HashAlgorithm::None {
+        tracing::error!("Checksum files cannot be verified with --hash-algorithm none.");
+        std::process::exit(1);
+    }
+    match check_checksum_file(Path::new(checksum_file), algorithm, verbosity) {
+        Ok(true) => {}
+        Ok(false) => {
+            tracing::error!("Checksum verification failed for {}", checksum_file);
+            std::process::exit(1);
+        }
+        Err(e) => {
+            tracing::error!("Error reading checksum file {}: {}", checksum_file, e);
+            std::process::exit(1);
+        }
+    }
+}
+
+// Checks every file against its sidecar, exits the process unless all of them match
+fn verify_sidecars<'a>(
+    files: impl Iterator<Item = &'a String>,
+    algorithm: HashAlgorithm,
+    verbosity: Verbosity,
+) {
+    if algorithm == HashAlgorithm::None {
+        tracing::error!("Sidecars cannot be verified with --hash-algorithm none.");
+        std::process::exit(1);
+    }
+    let mut all_ok = true;
+    for file in files {
+        match read_and_verify_sidecar(Path::new(file), algorithm) {
+            Ok(true) => {
+                if verbosity.prints_text() {
+                    println!("{}: {}", file, "OK".green());
+                }
+            }
+            Ok(false) => {
+                if verbosity.prints_reports() {
+                    println!("{}: {}", file, "MISMATCH".red());
+                }
+                all_ok = false;
+            }
+            Err(e) => {
+                if verbosity.prints_reports() {
+                    println!("{}: {} ({})", file, "MISMATCH".red(), e);
+                }
+                all_ok = false;
+            }
+        }
+    }
+    if !all_ok {
+        tracing::error!("Sidecar verification failed");
+        std::process::exit(1);
+    }
+}
+
+// Expands every {n} or zero-padded {n:0W} placeholder in a file name template
+fn expand_template(template: &str, n: usize) -> String {
+    let mut expanded = String::new();
+    let mut rest = template;
+
+    while let Some(start) = rest.find("{n") {
+        let Some(len) = rest[start..].find('}') else {
+            break;
+        };
+        let spec = &rest[start + 2..start + len];
+        let width = match spec.strip_prefix(":0") {
+            Some(width) => width.parse().unwrap_or(0),
+            None if spec.is_empty() => 0,
+            // Not a placeholder, keep it verbatim
+            None => {
+                expanded.push_str(&rest[..start + len + 1]);
+                rest = &rest[start + len + 1..];
+                continue;
+            }
+        };
+        expanded.push_str(&rest[..start]);
+        expanded.push_str(&format!("{:0width$}", n));
+        rest = &rest[start + len + 1..];
+    }
+
+    expanded.push_str(rest);
+    expanded
+}
+
+fn run_generate(matches: &ArgMatches) -> io::Result<()> {
+    let template = matches.get_one::<String>("file").unwrap();
+    let size = *matches.get_one::<usize>("size").unwrap();
+    let count = *matches.get_one::<usize>("count").unwrap();
+    let verbosity = Verbosity::from_matches(matches);
+    let seed = matches
+        .get_one::<u64>("seed")
+        .copied()
+        .unwrap_or_else(rand::random::<u64>);
+    let content = match matches.get_one::<String>("content").map(String::as_str) {
+        Some("binary") => ContentKind::Binary,
+        _ => ContentKind::Text,
+    };
+
+    if count > 1 && expand_template(template, 1) == expand_template(template, 2) {
+        tracing::error!(
+            "The file name must contain a {{n}} placeholder to generate {} files.",
+            count
+        );
+        std::process::exit(1);
+    }
+    // Total size of the batch, shown by the progress bar
+    let Some(total_size) = count
+        .checked_mul(size)
+        .and_then(|total| u64::try_from(total).ok())
+    else {
+        tracing::error!(
+            "--count {} files of --size {} bytes overflow the total size of the batch, generate fewer or smaller files.",
+            count,
+            size
+        );
+        std::process::exit(1);
+    };
+
+    let output_dir = matches.get_one::<String>("output-dir").map(Path::new);
+    if let Some(dir) = output_dir {
+        std::fs::create_dir_all(dir)?;
+    }
+
+    if verbosity.prints_text() {
+        println!("Seed: {}", seed);
+    }
+    let files: Vec<String> = (1..=count)
+        .map(|n| match output_dir {
+            Some(dir) => dir
+                .join(expand_template(template, n))
+                .to_string_lossy()
+                .into_owned(),
+            None => expand_template(template, n),
+        })
+        .collect();
+    let compressibility = *matches.get_one::<u8>("compressibility").unwrap();
+    let block_size = matches
+        .get_one::<usize>("gen-block-size")
+        .copied()
+        .unwrap_or(GENERATE_BLOCK_SIZE);
+    let force = matches.get_flag("force");
+    let hash = hash_algorithm(matches);
+    let hash_threads = matches.get_one::<usize>("parallel-hash").copied();
+    if hash_threads.is_some() && hash != HashAlgorithm::Blake3 {
+        tracing::warn!(
+            "--parallel-hash only applies to --hash-algorithm blake3, {} is computed on one thread",
+            hash.label()
+        );
+    }
+    let hash_threads = hash_threads.unwrap_or(1);
+
+    // A batch is spread over worker threads that each take the next file, never more threads than cores
+    let cores = std::thread::available_parallelism().map_or(1, usize::from);
+    let threads = matches
+        .get_one::<usize>("gen-threads")
+        .map_or(cores, |&threads| threads.min(cores))
+        .min(count)
+        .max(1);
+    tracing::debug!("Generating {} files on {} threads", count, threads);
+    // A single bar covers the whole batch
+    let bar = transfer_bar(Some(total_size), show_progress(matches));
+    let next = std::sync::atomic::AtomicUsize::new(0);
+    let results: Mutex<Vec<Option<io::Result<String>>>> =
+        Mutex::new((0..count).map(|_| None).collect());
+    std::thread::scope(|scope| {
+        for _ in 0..threads {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let Some(file) = files.get(index) else {
+                    break;
+                };
+                // Every file of a batch gets its own seed so the contents differ
+                let options = GenerateOptions {
+                    seed: seed.wrapping_add(index as u64),
+                    content,
+                    compressibility,
+                    force,
+                    progress: bar.clone(),
+                    block_size,
+                    hash,
+                    hash_threads,
+                };
+                let result = generate_random_text_file(Path::new(file), size, &options);
+                results.lock().unwrap_or_else(|e| e.into_inner())[index] = Some(result);
+            });
+        }
+    });
+
+    // Results are reported in file order, whichever thread finished first
+    let mut manifest = Vec::new();
+    let results = results.into_inner().unwrap_or_else(|e| e.into_inner());
+    let failed = results.iter().any(|result| matches!(result, Some(Err(_))));
+    finish_bar(&bar, &if failed { Err(()) } else { Ok(()) });
+    for (file, result) in files.into_iter().zip(results) {
+        match result.expect("every file is generated") {
+            Ok(file_hash) => {
+                if verbosity.prints_text() {
+                    println!("{}: {}", hash.label(), file_hash);
+                } else if verbosity.prints_bare() {
+                    println!("{}", file_hash);
+                }
+                if let Err(e) = write_hash_sidecar(Path::new(&file), hash, &file_hash) {
+                    tracing::error!("Error writing the hash of {}: {}", file, e);
+                }
+                manifest.push((file, file_hash));
+            }
+            Err(e) => tracing::error!("Error generating file {}: {}", file, e),
+        }
+    }
+
+    if count > 1 && verbosity.prints_text() {
+        println!("Manifest:");
+        for (file, hash) in &manifest {
+            println!("{}  {}  {}", hash, human_size(size as u64), file);
+        }
+    }
+
+    if let Some(manifest_file) = matches.get_one::<String>("manifest") {
+        let mut writer = File::create(manifest_file)?;
+        for (file, hash) in &manifest {
+            writeln!(writer, "{}  {}", hash, file)?;
+        }
+        tracing::info!("Manifest written to: {}", manifest_file);
+    }
+
+    Ok(())
+}
+
+fn run_list(
+    client: &HttpClient,
+    server: &str,
+    matches: &ArgMatches,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let filter = matches
+        .get_one::<String>("filter")
+        .map(|p| glob::Pattern::new(p))
+        .transpose()?;
+    let endpoint = matches.get_one::<String>("list-endpoint").unwrap();
+    let verbosity = Verbosity::from_matches(matches);
+
+    match list_files(client, server, endpoint) {
+        Ok(mut files) => {
+            if let Some(pattern) = &filter {
+                files.retain(|f| pattern.matches(&f.name));
+            }
+            if matches.get_flag("json") {
+                if verbosity.prints_reports() {
+                    println!("{}", serde_json::to_string_pretty(&files)?);
+                }
+            } else if verbosity.prints_text() {
+                print_file_table(&files);
+            }
+        }
+        Err(e) => {
+            tracing::error!("Error listing files: {}", e);
+            std::process::exit(1);
+        }
+    }
+    Ok(())
+}
+
+fn run_exists(client: &HttpClient, server: &str, matches: &ArgMatches) {
+    let file = matches.get_one::<String>("file").unwrap();
+    let verbosity = Verbosity::from_matches(matches);
+
+    match file_exists_on_server(client, &head_url(server, file, matches)) {
+        Ok(true) => {
+            if verbosity.prints_text() {
+                println!("{}: {}", file, "exists".green());
+            }
+        }
+        Ok(false) => {
+            if verbosity.prints_text() {
+                println!("{}: {}", file, "not found".red());
+            }
+            std::process::exit(1);
+        }
+        Err(e) => {
+            tracing::error!("Error checking file {}: {}", file, e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn run_delete(
+    client: &HttpClient,
+    server: &str,
+    matches: &ArgMatches,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(pattern) = matches.get_one::<String>("glob") {
+        return run_delete_glob(client, server, pattern, matches);
+    }
+    let file = matches.get_one::<String>("file").unwrap();
+    let path = matches.get_one::<String>("delete-path").unwrap();
+
+    if !matches.get_flag("yes") && !confirm(&format!("Delete {} from {}?", file, server)) {
+        tracing::info!("{}: Not deleted", file);
+        return Ok(());
+    }
+    match delete_file(client, server, path, file, matches.get_flag("encode-slash"))
+        .map_err(DownloadError::from)
+        .and_then(|response| check_status(client, response))
+    {
+        Ok(response) => {
+            tracing::info!("{}: Deleted. Status: {}", file, response.status());
+            client.dump_response(response);
+        }
+        Err(e) => {
+            tracing::error!("Error deleting file {}: {}", file, e);
+            std::process::exit(1);
+        }
+    }
+    Ok(())
+}
+
+// Deletes every file of the server listing whose name matches the glob pattern
+fn run_delete_glob(
+    client: &HttpClient,
+    server: &str,
+    pattern: &str,
+    matches: &ArgMatches,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let glob = glob::Pattern::new(pattern)?;
+    let endpoint = matches.get_one::<String>("list-endpoint").unwrap();
+    let path = matches.get_one::<String>("delete-path").unwrap();
+
+    let files: Vec<ServerFile> = match list_files(client, server, endpoint) {
+        Ok(files) => files
+            .into_iter()
+            .filter(|f| glob.matches(&f.name))
+            .collect(),
+        Err(e) => {
+            tracing::error!("Error listing files: {}", e);
+            std::process::exit(1);
+        }
+    };
+    if files.is_empty() {
+        tracing::info!("No files match {}", pattern);
+        return Ok(());
+    }
+    for file in &files {
+        tracing::info!("{} ({})", file.name, human_size(file.size));
+    }
+
+    // Too destructive to go ahead unattended without --yes
+    if !matches.get_flag("yes") {
+        let question = format!(
+            "Delete {} files matching {} from {}?",
+            files.len(),
+            pattern,
+            server
+        );
+        if !confirm(&question) {
+            tracing::info!("No files deleted");
+            return Ok(());
+        }
+    }
+
+    let mut failed = 0;
+    for file in &files {
+        match delete_file(
+            client,
+            server,
+            path,
+            &file.name,
+            matches.get_flag("encode-slash"),
+        )
+        .map_err(DownloadError::from)
+        .and_then(|response| check_status(client, response))
+        {
+            Ok(response) => {
+                tracing::info!("{}: Deleted. Status: {}", file.name, response.status());
+                client.dump_response(response);
+            }
+            Err(e) => {
+                tracing::error!("Error deleting file {}: {}", file.name, e);
+                failed += 1;
+            }
+        }
+    }
+    tracing::info!(
+        "Deleted {} of {} files matching {}",
+        files.len() - failed,
+        files.len(),
+        pattern
+    );
+    if failed > 0 {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+// Asks on the terminal. Without a terminal on standard input nobody can answer, so the run is
+// aborted and scripts have to pass --yes.
+fn confirm(question: &str) -> bool {
+    if !io::stdin().is_terminal() {
+        tracing::error!("Deleting requires --yes when not run on a terminal.");
+        std::process::exit(1);
+    }
+    eprint!("{} [y/N] ", question);
+    let mut answer = String::new();
+    if io::stdin().lock().read_line(&mut answer).is_err() {
+        return false;
+    }
+    matches!(answer.trim().to_ascii_lowercase().as_str(), "y" | "yes")
+}
+
+// Pre-download checks requested on the command line, exits the process if one fails
+fn check_before_download(client: &HttpClient, server: &str, file: &str, matches: &ArgMatches) {
+    if matches.get_flag("assert-exists") {
+        match file_exists_on_server(client, &head_url(server, file, matches)) {
+            Ok(true) => {}
+            Ok(false) => {
+                tracing::error!("File not found on server: {}", file);
+                std::process::exit(1);
+            }
+            Err(e) => {
+                tracing::error!("Error checking file {}: {}", file, e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    // Pre-flight size check, the size limits require a known remote size. Without them the size
+    // is only requested when it is shown, which -q turns off.
+    let min_size = matches.get_one::<u64>("min-size").copied();
+    let max_size = matches.get_one::<u64>("max-size").copied();
+    if min_size.is_none() && max_size.is_none() && !Verbosity::from_matches(matches).prints_text() {
+        return;
+    }
+    match remote_file_size(client, &head_url(server, file, matches)) {
+        Ok(remote_size) => {
+            // MB as in --min-size and --max-size, 1024 * 1024 bytes
+            tracing::info!(
+                "Remote file size: {} bytes ({:.2} MB)",
+                remote_size,
+                remote_size as f64 / (1024.0 * 1024.0)
+            );
+            if min_size.is_some_and(|min| remote_size < min)
+                || max_size.is_some_and(|max| remote_size > max)
+            {
+                tracing::error!(
+                    "Remote file size {} is outside the allowed range, aborting download of {}",
+                    human_size(remote_size),
+                    file
+                );
+                std::process::exit(1);
+            }
+        }
+        Err(e) if min_size.is_some() || max_size.is_some() => {
+            tracing::error!("Error getting remote size of {}: {}", file, e);
+            std::process::exit(1);
+        }
+        Err(e) => tracing::warn!("Unable to determine remote size of {}: {}", file, e),
+    }
+}
+
+// Exits the process if the download target exists and may not be replaced
+fn ensure_can_write(path: &Path, overwrite: bool) {
+    if !overwrite && path.exists() {
+        tracing::error!(
+            "Output file {} already exists, use --overwrite to replace it.",
+            path.display()
+        );
+        std::process::exit(1);
+    }
+}
+
+// Expands {n} (the iteration number) and {timestamp} in an --output-template
+fn expand_output_template(dir: Option<&Path>, template: &str, n: usize) -> PathBuf {
+    let timestamp = chrono::Local::now().format("%Y%m%d-%H%M%S%.3f").to_string();
+    let name = expand_template(template, n).replace("{timestamp}", &timestamp);
+    match dir {
+        Some(dir) => dir.join(name),
+        None => PathBuf::from(name),
+    }
+}
+
+// Set by the first Ctrl-C, transfer loops stop after the transfer in flight and print their stats
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+fn install_interrupt_handler() {
+    let result = ctrlc::set_handler(|| {
+        if INTERRUPTED.swap(true, Ordering::SeqCst) {
+            // A second Ctrl-C aborts the transfer in flight
+            std::process::exit(130);
+        }
+        tracing::warn!(
+            "Interrupted, stopping after the current transfer, press Ctrl-C again to abort"
+        );
+    });
+    if let Err(e) = result {
+        tracing::warn!("Unable to install the Ctrl-C handler: {}", e);
+    }
+}
+
+fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+fn hash_algorithm(matches: &ArgMatches) -> HashAlgorithm {
+    matches
+        .get_one::<String>("hash-algorithm")
+        .and_then(|name| HashAlgorithm::from_name(name))
+        .unwrap_or(HashAlgorithm::Sha256)
+}
+
+// Transfers taking longer than this are reported as warnings, which are shown in yellow
+fn slow_threshold(matches: &ArgMatches) -> Duration {
+    Duration::from_secs_f64(*matches.get_one::<f64>("slow-threshold").unwrap())
+}
+
+fn warn_if_slow(name: &str, duration: Duration, slow_threshold: Duration) {
+    if duration > slow_threshold {
+        tracing::warn!(
+            "{}: Slow transfer, took {} (threshold {:.2?})",
+            name,
+            format!("{:.2?}", duration).yellow(),
+            slow_threshold
+        );
+    }
+}
+
+// Upload settings from the options of the upload and roundtrip subcommands, roundtrip has fewer
+fn upload_options(matches: &ArgMatches, timeout: u64) -> UploadOptions<'_> {
+    UploadOptions {
+        method: upload_method(matches),
+        timeout_secs: timeout,
+        field_name: matches
+            .try_get_one::<String>("field-name")
+            .ok()
+            .flatten()
+            .map_or("file", String::as_str),
+        remote_name: matches
+            .try_get_one::<String>("remote-name")
+            .ok()
+            .flatten()
+            .map(String::as_str),
+        part_size: matches
+            .try_get_one::<usize>("upload-chunk-size")
+            .ok()
+            .flatten()
+            .map(|&size| size as u64),
+        content_type: matches
+            .try_get_one::<String>("content-type")
+            .ok()
+            .flatten()
+            .map(String::as_str),
+        gzip: matches
+            .try_get_one::<String>("compress")
+            .is_ok_and(|compress| compress.is_some_and(|method| method == "gzip"))
+            || matches
+                .try_get_one::<bool>("compress-upload")
+                .is_ok_and(|compress| compress == Some(&true)),
+        path: matches
+            .try_get_one::<String>("upload-path")
+            .ok()
+            .flatten()
+            .map(String::as_str),
+        encode_slash: matches.get_flag("encode-slash"),
+        hash: hash_algorithm(matches),
+    }
+}
+
+// Runs the upload and/or download of a file for the requested number of iterations
+fn run_transfers(
+    client: &HttpClient,
+    server: &str,
+    matches: &ArgMatches,
+    iterations: usize,
+    timeout: u64,
+    upload: bool,
+    download: bool,
+) {
+    let file = matches.get_one::<String>("file").unwrap();
+    let verbosity = Verbosity::from_matches(matches);
+    let upload_options = upload_options(matches, timeout);
+    let delete_path = matches.get_one::<String>("delete-path").unwrap();
+    // Several files are sent together in a single multipart request
+    let upload_names: Vec<&str> = matches
+        .get_many::<String>("file")
+        .unwrap()
+        .map(String::as_str)
+        .collect();
+
+    if upload && upload_names.len() > 1 && upload_options.remote_name.is_some() {
+        tracing::error!("--remote-name can only be used when uploading a single file.");
+        std::process::exit(1);
+    }
+    if upload
+        && upload_options.part_size.is_some()
+        && (upload_names.len() > 1 || upload_names == ["-"])
+    {
+        tracing::error!("--upload-chunk-size can only be used when uploading a single file.");
+        std::process::exit(1);
+    }
+    if upload
+        && upload_options.method == UploadMethod::Put
+        && (upload_names.len() > 1 || upload_names == ["-"])
+    {
+        tracing::error!("--method put can only be used when uploading a single file.");
+        std::process::exit(1);
+    }
+    if upload && upload_names.len() > 1 && upload_options.path.is_some_and(has_name_placeholder) {
+        tracing::error!(
+            "--upload-path cannot contain {{name}} or {{file}} when uploading several files in one request."
+        );
+        std::process::exit(1);
+    }
+    if upload && upload_options.gzip && (upload_names.len() > 1 || upload_names == ["-"]) {
+        tracing::error!(
+            "--compress and --compress-upload can only be used when uploading a single file."
+        );
+        std::process::exit(1);
+    }
+    // Standard input can only be read once
+    if upload && upload_names.contains(&"-") {
+        if upload_names.len() > 1 || upload_options.remote_name.is_none() {
+            tracing::error!(
+                "Uploading from standard input requires a single '-' file and --remote-name."
+            );
+            std::process::exit(1);
+        }
+        if download || iterations > 1 {
+            tracing::error!("Standard input can only be uploaded once, use the upload command without --iterations.");
+            std::process::exit(1);
+        }
+    }
+
+    if upload {
+        if let Some(checksum_file) = matches.get_one::<String>("check") {
+            verify_checksums(checksum_file, upload_options.hash, verbosity);
+        }
+    }
+
+    let output_dir = matches
+        .try_get_one::<String>("output-dir")
+        .ok()
+        .flatten()
+        .map(Path::new);
+    let output_template = matches
+        .try_get_one::<String>("output-template")
+        .ok()
+        .flatten()
+        .filter(|_| download);
+    let output = match matches.try_get_one::<String>("output") {
+        Ok(Some(output)) if download => Some(resolve_output_path(output, file)),
+        _ => match output_dir {
+            Some(dir) if download && output_template.is_none() => {
+                Some(sanitize_relative_path(file).map(|path| dir.join(path)))
+            }
+            _ => None,
+        },
+    }
+    .transpose()
+    .unwrap_or_else(|e| {
+        tracing::error!("Invalid output path for {}: {}", file, e);
+        std::process::exit(1);
+    });
+    let to_stdout = output.as_deref() == Some(Path::new(STDOUT_PATH));
+    if to_stdout && iterations > 1 {
+        tracing::error!("Only a single download can be written to standard output.");
+        std::process::exit(1);
+    }
+    if let Some(template) = output_template {
+        if iterations > 1
+            && expand_template(template, 1) == expand_template(template, 2)
+            && !template.contains("{timestamp}")
+        {
+            tracing::error!("The output template needs a {{n}} or {{timestamp}} placeholder for several iterations.");
+            std::process::exit(1);
+        }
+    }
+
+    let parallel_chunks = match matches.try_get_one::<usize>("parallel-chunks") {
+        Ok(Some(&chunks)) if download => chunks,
+        _ => 1,
+    };
+    if parallel_chunks > 1 && (to_stdout || (output.is_none() && output_template.is_none())) {
+        tracing::error!("--parallel-chunks needs an output file to write the ranges to.");
+        std::process::exit(1);
+    }
+    let save_hash = download && matches.get_flag("save-hash");
+    if save_hash && (to_stdout || (output.is_none() && output_template.is_none())) {
+        tracing::error!("--save-hash needs an output file to write the sidecar next to.");
+        std::process::exit(1);
+    }
+
+    let min_speed = match matches.try_get_one::<u64>("min-speed") {
+        Ok(Some(&speed)) if download && speed > 0 => Some((
+            speed,
+            Duration::from_secs(*matches.get_one::<u64>("min-speed-timeout").unwrap()),
+        )),
+        _ => None,
+    };
+
+    // Existing files are only replaced with --overwrite, or continued with --continue. Checked
+    // before any request is sent.
+    let resume = download && matches.get_flag("continue");
+    let overwrite = download && (matches.get_flag("overwrite") || resume);
+    if let Some(path) = output.as_ref().filter(|_| !to_stdout) {
+        ensure_can_write(path, overwrite);
+    }
+
+    // Hash the reference file once, every download is compared against it
+    let verify = matches.try_get_one::<String>("verify");
+    let known_hash = match matches
+        .try_get_one::<String>("expected-hash")
+        .ok()
+        .flatten()
+    {
+        Some(hash) => Some(hash.clone()),
+        None if download && !upload => manifest_hash(matches, file, upload_options.hash),
+        None => None,
+    }
+    .filter(|_| download);
+    if download
+        && upload_options.hash == HashAlgorithm::None
+        && (verify.as_ref().is_ok_and(Option::is_some) || known_hash.is_some())
+    {
+        tracing::error!("--verify and --expected-hash cannot be used with --hash-algorithm none.");
+        std::process::exit(1);
+    }
+    let expected_hash = match verify {
+        Ok(Some(local)) if download => match hash_file(Path::new(local), upload_options.hash) {
+            Ok(hash) => Some((local, hash)),
+            Err(e) => {
+                tracing::error!("Error hashing local file {}: {}", local, e);
+                std::process::exit(1);
+            }
+        },
+        _ => None,
+    };
+    // Iterations with a failed transfer, an unaccepted status or a hash mismatch
+    let mut failed_iterations = 0;
+    let slow_threshold = slow_threshold(matches);
+    let mut csv_report = csv_report(client, matches);
+    let timing = matches.get_flag("timing");
+    let write_out = matches.get_one::<WriteOut>("write-out");
+    // Written once all iterations are done, downloads are verified against it instead
+    let upload_manifest = matches
+        .try_get_one::<String>("manifest")
+        .ok()
+        .flatten()
+        .filter(|_| upload && !download);
+    let mut manifest = Manifest::new();
+
+    let cache = match matches.try_get_one::<String>("cache-dir") {
+        Ok(Some(dir)) if download => {
+            if upload_options.hash == HashAlgorithm::None {
+                tracing::error!("--cache-dir cannot be used with --hash-algorithm none.");
+                std::process::exit(1);
+            }
+            match DownloadCache::open(Path::new(dir), server, upload_options.hash) {
+                Ok(cache) => Some(cache),
+                Err(e) => {
+                    tracing::error!("Error opening the download cache {}: {}", dir, e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        _ => None,
+    };
+    let mut cache_hits = 0;
+
+    let upload_condition = UploadCondition::from_matches(matches).filter(|_| upload);
+    if upload_condition.is_some() && (upload_names.len() > 1 || upload_names == ["-"]) {
+        tracing::error!("--upload-if-absent and --upload-if-changed need a single file.");
+        std::process::exit(1);
+    }
+
+    // A roundtrip compares every download with the file it uploaded, hashed once up front.
+    // Standard input is hashed while it is sent.
+    let roundtrip_hash = match upload_names.as_slice() {
+        [name]
+            if upload && download && *name != "-" && upload_options.hash != HashAlgorithm::None =>
+        {
+            match hash_file(Path::new(name), upload_options.hash) {
+                Ok(hash) => Some(hash),
+                Err(e) => {
+                    tracing::error!("Error hashing local file {}: {}", name, e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        _ => None,
+    };
+
+    let mut upload_durations = Vec::new();
+    let mut download_durations = Vec::new();
+    let mut upload_stats = OperationStats::new("Uploads");
+    let mut download_stats = OperationStats::new("Downloads");
+    let mut pacer = OperationPacer::from_matches(matches);
+    let run_start = Instant::now();
+    // Server listing of --upload-if-changed, fetched once and again only after an upload changed it
+    let mut listing = None;
+    // The remote file is checked before the first download only, a roundtrip uploads it first
+    let mut download_checked = false;
+
+    for iteration in 1..=iterations {
+        if let Some(pacer) = pacer.as_mut() {
+            pacer.wait();
+        }
+        if interrupted() {
+            tracing::warn!(
+                "Stopped after {} of {} iterations",
+                iteration - 1,
+                iterations
+            );
+            break;
+        }
+
+        // Outcome of the iteration, printed as OK/FAIL or the download hash with -q
+        let mut passed = true;
+        let mut download_hash = None;
+        // Hash of the data uploaded in this iteration, only a successful upload is compared
+        let mut uploaded_hash = None;
+        let output = match output_template {
+            Some(template) => {
+                let path = expand_output_template(output_dir, template, iteration);
+                ensure_can_write(&path, overwrite);
+                Some(path)
+            }
+            None => output.clone(),
+        };
+
+        // Checked before the delete that would otherwise remove the file
+        let skip_reason = upload_condition.and_then(|condition| {
+            let _span = tracing::info_span!("upload", iteration).entered();
+            let remote_name = remote_file_name(Path::new(file), &upload_options).ok()?;
+            let reason = condition.skip_reason(
+                client,
+                server,
+                Path::new(file),
+                &remote_name,
+                &mut listing,
+            )?;
+            tracing::info!("{}: Upload skipped, {}", remote_name, reason);
+            Some(reason)
+        });
+        if skip_reason.is_some() {
+            // Recorded as a zero-duration upload so the iterations stay comparable across runs
+            upload_durations.push(Duration::ZERO);
+            upload_stats.succeeded(Duration::ZERO, 0);
+            if let Some(report) = csv_report.as_mut() {
+                let mut row = CsvRow::new("upload", file, "skipped".to_string(), Duration::ZERO);
+                row.size_bytes = Path::new(file).metadata().map(|m| m.len()).ok();
+                report.write(row);
+            }
+        }
+
+        if upload && skip_reason.is_none() {
+            let _span = tracing::info_span!("upload", iteration).entered();
+            listing = None;
+
+            // Attempt to delete the files from the server before uploading
+            for file in &upload_names {
+                delete_before_upload(
+                    client,
+                    server,
+                    delete_path,
+                    upload_options.remote_name.unwrap_or(file),
+                    upload_options.encode_slash,
+                );
+            }
+
+            // Proceed to upload the file
+            let files = upload_names.join(", ");
+            match client.upload_rate() {
+                Some(rate) => tracing::info!(
+                    "Start uploading file: {} (limited to {})",
+                    files,
+                    human_throughput(rate as f64)
+                ),
+                None => tracing::info!("Start uploading file: {}", files),
+            }
+            if let [file] = upload_names.as_slice() {
+                if upload_options.gzip {
+                    log_compression(Path::new(file));
+                }
+            }
+            let setup = setup_phases(client, server, timing);
+
+            // Record start time
+            let start_time = Instant::now();
+
+            // Only known up front for files, standard input is measured while it is sent
+            let mut upload_size = upload_names
+                .iter()
+                .filter(|&&name| name != "-")
+                .map(|name| Path::new(name).metadata().map(|m| m.len()).ok())
+                .sum::<Option<u64>>();
+            let mut upload_hash = None;
+
+            let result = match upload_names.as_slice() {
+                ["-"] => {
+                    upload_stdin(client, server, &upload_options).map(|(response, size, hash)| {
+                        tracing::info!("Sent {} from standard input", human_size(size));
+                        if verbosity.prints_text() {
+                            println!("{}: {}", upload_options.hash.label(), hash);
+                        }
+                        upload_size = Some(size);
+                        upload_hash = Some(hash);
+                        response
+                    })
+                }
+                [file] if upload_options.gzip => with_retries(client.retries(), || {
+                    upload_compressed(client, server, Path::new(file), &upload_options)
+                })
+                .map(|upload| {
+                    let duration = start_time.elapsed();
+                    tracing::info!(
+                        "{}: Sent {} compressed, {} on the wire",
+                        file,
+                        human_size(upload.compressed_size),
+                        human_throughput(upload.compressed_size as f64 / duration.as_secs_f64())
+                    );
+                    if verbosity.prints_text() {
+                        println!("{}: {}", upload_options.hash.label(), upload.hash);
+                    }
+                    upload_size = Some(upload.compressed_size);
+                    upload_hash = Some(upload.hash);
+                    upload.response
+                }),
+                // Parts are retried one by one
+                [file] if upload_options.part_size.is_some() => upload_file_in_parts(
+                    client,
+                    server,
+                    Path::new(file),
+                    upload_options.part_size.unwrap(),
+                    &upload_options,
+                ),
+                [file] => with_retries(client.retries(), || {
+                    upload_file(client, server, Path::new(file), &upload_options)
+                }),
+                _ => {
+                    let paths: Vec<&Path> = upload_names.iter().map(Path::new).collect();
+                    with_retries(client.retries(), || {
+                        upload_files(client, server, &paths, &upload_options)
+                    })
+                }
+            };
+            let phases = PhaseTimings {
+                ttfb: result.as_ref().ok().and_then(http::response_wait),
+                ..PhaseTimings::default()
+            }
+            .with_setup(setup);
+            if let Some(report) = csv_report.as_mut() {
+                let mut row = CsvRow::new(
+                    "upload",
+                    &files,
+                    csv_status(result.as_ref().map(Response::status)),
+                    start_time.elapsed(),
+                );
+                row.size_bytes = upload_size;
+                row.sha256 = upload_hash.as_deref();
+                row.set_phases(&phases);
+                report.write(row);
+            }
+            if let Some(write_out) = write_out {
+                let remote_name = remote_file_name(Path::new(upload_names[0]), &upload_options)
+                    .unwrap_or_default();
+                // Plain uploads are not hashed while sending, only for a format that shows the hash
+                let hash = match upload_names.as_slice() {
+                    [name]
+                        if upload_hash.is_none()
+                            && *name != "-"
+                            && write_out.uses(Variable::Hash) =>
+                    {
+                        hash_file(Path::new(name), upload_options.hash).ok()
+                    }
+                    _ => upload_hash.clone(),
+                };
+                let values = WriteOutValues {
+                    operation: "upload",
+                    filename: &files,
+                    url: &upload_url(server, &upload_options, &remote_name),
+                    status: result
+                        .as_ref()
+                        .ok()
+                        .map(|response| response.status().as_u16()),
+                    size: upload_size,
+                    hash: hash.as_deref(),
+                    total: start_time.elapsed(),
+                    phases: &phases,
+                };
+                print_write_out(write_out, &values, to_stdout);
+            }
+            passed &= result
+                .as_ref()
+                .is_ok_and(|response| client.accepts(response.status()));
+            match result {
+                Ok(response) if !client.accepts(response.status()) => {
+                    upload_stats.failed();
+                    tracing::error!(
+                        "Error uploading file {}: {}",
+                        files,
+                        rejected_status(client, response)
+                    )
+                }
+                Ok(response) => {
+                    // Calculate the duration and store it
+                    let duration = start_time.elapsed();
+                    upload_durations.push(duration);
+                    upload_stats.succeeded(duration, upload_size.unwrap_or(0));
+                    if upload_names.len() == 1 && upload_options.hash != HashAlgorithm::None {
+                        uploaded_hash = upload_hash.clone().or_else(|| roundtrip_hash.clone());
+                    }
+                    tracing::info!(
+                        "{}: Uploaded. Status: {} Time taken: {:.2?}",
+                        files,
+                        response.status().to_string().green(),
+                        duration
+                    );
+                    warn_if_slow(&files, duration, slow_threshold);
+                    if timing {
+                        tracing::info!("{}: Timing: {}", files, phases);
+                    }
+                    if upload_manifest.is_some() {
+                        for name in &upload_names {
+                            let Ok(remote_name) =
+                                remote_file_name(Path::new(name), &upload_options)
+                            else {
+                                continue;
+                            };
+                            match (*name, &upload_hash, upload_size) {
+                                // Standard input cannot be read again, its hash was computed while sending
+                                ("-", Some(hash), Some(size)) => manifest.add(ManifestEntry::new(
+                                    &remote_name,
+                                    upload_options.hash,
+                                    hash,
+                                    size,
+                                )),
+                                _ => add_to_manifest(
+                                    &mut manifest,
+                                    &remote_name,
+                                    Path::new(name),
+                                    upload_options.hash,
+                                ),
+                            }
+                        }
+                    }
+                    client.dump_response(response);
+                }
+                Err(e) => {
+                    upload_stats.failed();
+                    tracing::error!("Error uploading file {}: {}", files, e)
+                }
+            }
+        }
+
+        if download {
+            let _span = tracing::info_span!("download", iteration).entered();
+            // Tracks the download alone until it is tallied, then the upload outcome is added back
+            let upload_passed = std::mem::replace(&mut passed, true);
+            if !std::mem::replace(&mut download_checked, true) {
+                check_before_download(client, server, file, matches);
+            }
+
+            let chunked = matches.get_flag("chunked");
+            tracing::info!("Start downloading file: {}", file);
+            let setup = setup_phases(client, server, timing);
+
+            // Record start time
+            let start_time = Instant::now();
+
+            let download_path = if chunked {
+                "download-chunked-path"
+            } else {
+                "download-path"
+            };
+            let download_options = DownloadOptions {
+                path: matches.get_one::<String>(download_path).unwrap(),
+                encode_slash: upload_options.encode_slash,
+                output: output.as_deref(),
+                resume,
+                parallel_chunks,
+                min_speed,
+                accept_encoding: if matches.get_flag("decompress-download") {
+                    Some("gzip, deflate, br")
+                } else {
+                    matches
+                        .get_one::<String>("accept-encoding")
+                        .map(String::as_str)
+                },
+                hash: upload_options.hash,
+                cache: cache.as_ref(),
+            };
+            let result = with_retries(client.retries(), || {
+                download_file(client, server, file, &download_options).and_then(|downloaded| {
+                    match &known_hash {
+                        Some(expected) if downloaded.hash != *expected => {
+                            Err(DownloadError::HashMismatch {
+                                expected: expected.clone(),
+                                actual: downloaded.hash,
+                            })
+                        }
+                        _ => Ok(downloaded),
+                    }
+                })
+            });
+            if let Some(report) = csv_report.as_mut() {
+                let mut row = CsvRow::new(
+                    "download",
+                    file,
+                    csv_status(result.as_ref().map(|downloaded| downloaded.status)),
+                    start_time.elapsed(),
+                );
+                if let Ok(downloaded) = &result {
+                    row.size_bytes = Some(downloaded.size);
+                    row.sha256 = Some(&downloaded.hash);
+                    row.set_phases(&downloaded.phases.with_setup(setup));
+                }
+                report.write(row);
+            }
+            if let Some(write_out) = write_out {
+                let phases = match &result {
+                    Ok(downloaded) => downloaded.phases.with_setup(setup),
+                    Err(_) => PhaseTimings::default().with_setup(setup),
+                };
+                let values = WriteOutValues {
+                    operation: "download",
+                    filename: file,
+                    url: &endpoint_url(
+                        server,
+                        download_options.path,
+                        file,
+                        download_options.encode_slash,
+                    ),
+                    status: match &result {
+                        Ok(downloaded) => Some(downloaded.status.as_u16()),
+                        Err(e) => e.status(),
+                    },
+                    size: result.as_ref().ok().map(|downloaded| downloaded.size),
+                    hash: result
+                        .as_ref()
+                        .ok()
+                        .map(|downloaded| downloaded.hash.as_str()),
+                    total: start_time.elapsed(),
+                    phases: &phases,
+                };
+                print_write_out(write_out, &values, to_stdout);
+            }
+            match result {
+                Ok(Downloaded {
+                    size,
+                    resumed,
+                    wire_size,
+                    hash,
+                    status,
+                    phases,
+                    cached,
+                }) => {
+                    // 416 is only returned for a file that was already complete
+                    passed &= client.accepts(status)
+                        || status == reqwest::StatusCode::RANGE_NOT_SATISFIABLE
+                        || cached;
+                    // Calculate the duration and store it
+                    let duration = start_time.elapsed();
+                    download_durations.push(duration);
+                    if cached {
+                        cache_hits += 1;
+                        tracing::info!("{}: Cache hit, not modified on the server", file);
+                    }
+                    tracing::info!(
+                        "{}: {} chunked = {} Size = {} {}: {} Time taken: {:.2?} ({})",
+                        file,
+                        "Downloaded".green(),
+                        chunked,
+                        human_size(size),
+                        download_options.hash.label(),
+                        hash,
+                        duration,
+                        human_throughput((size - resumed) as f64 / duration.as_secs_f64())
+                    );
+                    if !client.expected_statuses().is_empty() {
+                        tracing::info!(
+                            "{}: Status {} as expected",
+                            file,
+                            status.to_string().green()
+                        );
+                    }
+                    if wire_size != size - resumed && !cached {
+                        tracing::info!(
+                            "{}: Received {} on the wire ({}), {} decoded ({:.2}x)",
+                            file,
+                            human_size(wire_size),
+                            human_throughput(wire_size as f64 / duration.as_secs_f64()),
+                            human_size(size - resumed),
+                            (size - resumed) as f64 / wire_size.max(1) as f64
+                        );
+                    }
+                    if resumed > 0 {
+                        tracing::info!(
+                            "{}: Resumed {} already on disk, transferred {}",
+                            file,
+                            human_size(resumed),
+                            human_size(size - resumed)
+                        );
+                    }
+                    warn_if_slow(file, duration, slow_threshold);
+                    if timing {
+                        tracing::info!("{}: Timing: {}", file, phases.with_setup(setup));
+                    }
+                    if let Some(path) = output.as_ref().filter(|_| !to_stdout) {
+                        tracing::info!("{}: Saved to {}", file, path.display());
+                        if save_hash {
+                            if let Err(e) = write_hash_sidecar(path, download_options.hash, &hash) {
+                                tracing::error!("{}: Error writing the hash: {}", file, e);
+                                passed = false;
+                            }
+                        }
+                    }
+                    if known_hash.is_some() {
+                        tracing::info!("{}: Hash verification: {}", file, "PASSED".green());
+                    }
+                    match &uploaded_hash {
+                        Some(uploaded) if hash != *uploaded => {
+                            tracing::error!(
+                                "{}: CORRUPTION DETECTED, downloaded {} {} does not match the uploaded {}",
+                                file,
+                                download_options.hash.label(),
+                                hash,
+                                uploaded
+                            );
+                            passed = false;
+                        }
+                        Some(_) => tracing::info!("{}: Matches the uploaded file", file),
+                        None => {}
+                    }
+                    if let Some((local, expected)) = &expected_hash {
+                        if hash == *expected {
+                            tracing::info!("{}: Verified, matches {}", file, local);
+                        } else {
+                            tracing::error!(
+                                "{}: Verification failed, {} {} does not match {} ({})",
+                                file,
+                                download_options.hash.label(),
+                                hash,
+                                local,
+                                expected
+                            );
+                            passed = false;
+                        }
+                    }
+                    if passed {
+                        download_stats.succeeded(duration, size - resumed);
+                    } else {
+                        download_stats.failed();
+                    }
+                    download_hash = Some(hash);
+                }
+                Err(e) => {
+                    passed = false;
+                    download_stats.failed();
+                    tracing::error!("Error downloading file {}: {}", file, e);
+                    // Pipelines only see the exit code
+                    if to_stdout {
+                        std::process::exit(1);
+                    }
+                }
+            }
+            passed &= upload_passed;
+        }
+
+        // A plain download reports its hash, anything that can fail a check reports OK/FAIL.
+        // --write-out replaces these results.
+        if verbosity.prints_bare() && !to_stdout && write_out.is_none() {
+            match download_hash
+                .filter(|_| !upload && expected_hash.is_none() && known_hash.is_none())
+            {
+                Some(hash) => println!("{}", hash),
+                None if passed => println!("OK"),
+                None => println!("FAIL"),
+            }
+        }
+        if !passed {
+            failed_iterations += 1;
+        }
+    }
+
+    // Calculate and print the average times
+    log_average("upload", &upload_durations, client.keepalive());
+    log_average("download", &download_durations, client.keepalive());
+    let elapsed = run_start.elapsed();
+    upload_stats.log(elapsed);
+    download_stats.log(elapsed);
+    if let Some(cache) = &cache {
+        if !download_durations.is_empty() {
+            tracing::info!(
+                "Cache hits: {} of {} downloads ({:.0}%), cached in {}",
+                cache_hits,
+                download_durations.len(),
+                cache_hits as f64 * 100.0 / download_durations.len() as f64,
+                cache.dir().display()
+            );
+        }
+    }
+    if let Some(pacer) = &pacer {
+        pacer.log_achieved("iterations");
+    }
+    if let Some(path) = upload_manifest {
+        write_manifest(&manifest, path);
+    }
+
+    if failed_iterations > 0 {
+        tracing::error!("{} of {} iterations failed", failed_iterations, iterations);
+        std::process::exit(1);
+    }
+    if interrupted() {
+        std::process::exit(130);
+    }
+}
+
+// Options of the blocking path that the async transfers of --async do not support
+const ASYNC_UNSUPPORTED: [&str; 33] = [
+    "recursive",
+    "stdin",
+    "upload-if-absent",
+    "upload-if-changed",
+    "upload-chunk-size",
+    "compress",
+    "compress-upload",
+    "check",
+    "manifest",
+    "verify",
+    "save-hash",
+    "output",
+    "output-dir",
+    "output-template",
+    "continue",
+    "parallel-chunks",
+    "cache-dir",
+    "min-speed",
+    "accept-encoding",
+    "decompress-download",
+    "assert-exists",
+    "min-size",
+    "max-size",
+    "retries",
+    "rate",
+    "limit-rate",
+    "upload-rate",
+    "download-rate",
+    "timing",
+    "write-out",
+    "audit-log",
+    "dump-headers",
+    "dump-body",
+];
+
+// Runs the iterations as concurrent async transfers for load tests. Downloads are hashed but not
+// saved, and files are not deleted before they are uploaded.
+fn run_async(
+    client: HttpClient,
+    server: &str,
+    matches: &ArgMatches,
+    iterations: usize,
+    timeout: u64,
+    upload: bool,
+    download: bool,
+) {
+    // Not every subcommand has all of them
+    let given = |id: &str| matches.ids().any(|present| present == id) && is_explicit(matches, id);
+    if let Some(id) = ASYNC_UNSUPPORTED.into_iter().find(|id| given(id)) {
+        tracing::error!("--{} cannot be used with --async.", id);
+        std::process::exit(1);
+    }
+    let file = matches.get_one::<String>("file").unwrap();
+    let hash = hash_algorithm(matches);
+
+    let upload = if upload {
+        let upload_options = upload_options(matches, timeout);
+        if matches.get_many::<String>("file").unwrap().len() > 1 || file == "-" {
+            tracing::error!("--async uploads a single file.");
+            std::process::exit(1);
+        }
+        // Read once, every request sends the same bytes
+        let body = std::fs::read(file).unwrap_or_else(|e| {
+            tracing::error!("Error reading {}: {}", file, e);
+            std::process::exit(1);
+        });
+        let file_name = remote_file_name(Path::new(file), &upload_options).unwrap_or_else(|e| {
+            tracing::error!("Error uploading {}: {}", file, e);
+            std::process::exit(1);
+        });
+        Some(concurrent::AsyncUpload {
+            url: upload_url(server, &upload_options, &file_name),
+            body: body.into(),
+            put: upload_options.method == UploadMethod::Put,
+            field_name: upload_options.field_name.to_string(),
+            file_name,
+            content_type: upload_options.content_type.map(str::to_string),
+        })
+    } else {
+        None
+    };
+
+    let download_url = download.then(|| {
+        let path = if matches.get_flag("chunked") {
+            "download-chunked-path"
+        } else {
+            "download-path"
+        };
+        endpoint_url(
+            server,
+            matches.get_one::<String>(path).unwrap(),
+            file,
+            matches.get_flag("encode-slash"),
+        )
+    });
+    // A roundtrip must download what it uploaded
+    let expected_hash = match (&upload, matches.try_get_one::<String>("expected-hash")) {
+        (_, Ok(Some(expected))) => Some(expected.clone()),
+        (Some(upload), _) if download && hash != HashAlgorithm::None => {
+            let mut hasher = hash.hasher();
+            hasher.update(&upload.body);
+            Some(hasher.finalize())
+        }
+        _ => None,
+    };
+
+    let csv_report = csv_report(&client, matches);
+    let run = concurrent::AsyncRun {
+        file: file.clone(),
+        operations: iterations,
+        concurrency: *matches.get_one::<usize>("concurrency").unwrap(),
+        timeout: Duration::from_secs(timeout),
+        upload,
+        download_url,
+        hash,
+        expected_hash,
+        slow_threshold: slow_threshold(matches),
+    };
+    if !concurrent::run(client, run, csv_report) {
+        std::process::exit(1);
+    }
+    if interrupted() {
+        std::process::exit(130);
+    }
+}
+
+// Starts operations at the --rate per second, however long each of them takes
+struct OperationPacer {
+    bucket: TokenBucket,
+    target: u64,
+    first_start: Option<Instant>,
+    last_start: Option<Instant>,
+    started: usize,
+}
+
+impl OperationPacer {
+    fn from_matches(matches: &ArgMatches) -> Option<Self> {
+        let target = *matches.try_get_one::<u64>("rate").ok().flatten()?;
+        Some(Self {
+            bucket: TokenBucket::pacing(target),
+            target,
+            first_start: None,
+            last_start: None,
+            started: 0,
+        })
+    }
+
+    // Blocks until the next operation may start
+    fn wait(&mut self) {
+        self.bucket.take(1);
+        let now = Instant::now();
+        self.first_start.get_or_insert(now);
+        self.last_start = Some(now);
+        self.started += 1;
+    }
+
+    // The rate is measured between the starts of the operations, so a slow last one does not skew it
+    fn log_achieved(&self, operations: &str) {
+        let (Some(first), Some(last)) = (self.first_start, self.last_start) else {
+            return;
+        };
+        if self.started < 2 {
+            return;
+        }
+        let achieved = (self.started - 1) as f64 / last.duration_since(first).as_secs_f64();
+        tracing::info!(
+            "Achieved rate: {:.2} {}/s (target {}/s)",
+            achieved,
+            operations,
+            self.target
+        );
+        // Operations slower than the interval delay the next start
+        if achieved < self.target as f64 * 0.95 {
+            tracing::warn!(
+                "The target rate was not reached, operations took longer than {:.2?} each",
+                Duration::from_secs_f64(1.0 / self.target as f64)
+            );
+        }
+    }
+}
+
+fn average(durations: &[Duration]) -> Duration {
+    durations.iter().copied().sum::<Duration>() / durations.len() as u32
+}
+
+// The client keeps connections open across iterations, so only the first transfer pays for the
+// TCP and TLS handshakes and is reported separately. With --no-keepalive every transfer pays for them.
+fn log_average(operation: &str, durations: &[Duration], keepalive: bool) {
+    match durations {
+        [] => {}
+        _ if !keepalive => tracing::info!(
+            "Average {} time: {:.2?} with a new connection per request",
+            operation,
+            average(durations)
+        ),
+        [_] => tracing::info!("Average {} time: {:.2?}", operation, average(durations)),
+        [first, rest @ ..] => tracing::info!(
+            "Average {} time: {:.2?}, {:.2?} with reused connections (first iteration {:.2?} including connection setup)",
+            operation,
+            average(durations),
+            average(rest),
+            first
+        ),
+    }
+}
+
+fn upload_method(matches: &ArgMatches) -> UploadMethod {
+    match matches.try_get_one::<String>("method") {
+        Ok(Some(method)) if method.eq_ignore_ascii_case("put") => UploadMethod::Put,
+        _ => UploadMethod::Post,
+    }
+}
+
+// Records an uploaded file in the --manifest, once since every iteration uploads the same file
+fn add_to_manifest(manifest: &mut Manifest, name: &str, path: &Path, algorithm: HashAlgorithm) {
+    if manifest.find(name).is_some() {
+        return;
+    }
+    match hash_file(path, algorithm).and_then(|hash| Ok((hash, path.metadata()?.len()))) {
+        Ok((hash, size)) => manifest.add(ManifestEntry::new(name, algorithm, &hash, size)),
+        Err(e) => tracing::warn!("{}: Not added to the manifest: {}", name, e),
+    }
+}
+
+fn write_manifest(manifest: &Manifest, path: &str) {
+    match manifest.write(Path::new(path)) {
+        Ok(()) => tracing::info!(
+            "Manifest of {} files written to: {}",
+            manifest.files.len(),
+            path
+        ),
+        Err(e) => {
+            tracing::error!("Error writing manifest {}: {}", path, e);
+            std::process::exit(1);
+        }
+    }
+}
+
+// Hash of a download in the manifest given with download --manifest, the run is aborted if the
+// manifest cannot be read or has no such hash for the file
+fn manifest_hash(matches: &ArgMatches, file: &str, algorithm: HashAlgorithm) -> Option<String> {
+    let path = matches.try_get_one::<String>("manifest").ok().flatten()?;
+    if algorithm == HashAlgorithm::None {
+        tracing::error!("--manifest cannot be used with --hash-algorithm none.");
+        std::process::exit(1);
+    }
+    let manifest = Manifest::read(Path::new(path)).unwrap_or_else(|e| {
+        tracing::error!("Error reading manifest {}: {}", path, e);
+        std::process::exit(1);
+    });
+    let Some(entry) = manifest.find(file) else {
+        tracing::error!("{} is not listed in manifest {}", file, path);
+        std::process::exit(1);
+    };
+    match entry.hash(algorithm) {
+        Some(hash) => Some(hash.to_ascii_lowercase()),
+        None => {
+            tracing::error!(
+                "Manifest {} has no {} for {}, see --hash-algorithm",
+                path,
+                algorithm.label(),
+                file
+            );
+            std::process::exit(1);
+        }
+    }
+}
+
+// Prints the --write-out line of an operation, to stderr while stdout carries a download
+fn print_write_out(write_out: &WriteOut, values: &WriteOutValues, to_stdout: bool) {
+    let line = write_out.render(values);
+    if to_stdout {
+        eprint!("{}", line);
+    } else {
+        print!("{}", line);
+        let _ = io::stdout().flush();
+    }
+}
+
+// Connection setup phases for --timing, probed on a connection of its own before an operation
+fn setup_phases(client: &HttpClient, server: &str, timing: bool) -> PhaseTimings {
+    if !timing {
+        return PhaseTimings::default();
+    }
+    if client.proxied() {
+        tracing::debug!("Connection setup is not timed through a proxy");
+        return PhaseTimings::default();
+    }
+    timing::probe_connection(server, client.connect_timeout())
+}
+
+// Opens the --csv report, the run is aborted if it cannot be created
+fn csv_report(client: &HttpClient, matches: &ArgMatches) -> Option<CsvReport> {
+    let path = matches.get_one::<String>("csv")?;
+    match CsvReport::create(Path::new(path), client.keepalive()) {
+        Ok(report) => Some(report),
+        Err(e) => {
+            tracing::error!("Error creating CSV report {}: {}", path, e);
+            std::process::exit(1);
+        }
+    }
+}
+
+// Status column of a CSV row, "error" when the request failed without a response
+fn csv_status<T>(status: Result<reqwest::StatusCode, T>) -> String {
+    match status {
+        Ok(status) => status.as_u16().to_string(),
+        Err(_) => "error".to_string(),
+    }
+}
+
+// Uploads every regular file below the given directories, each under its path relative to the directory.
+// Failed uploads do not stop the run, they are listed at the end and make the process exit non-zero.
+fn run_recursive_upload(
+    client: &HttpClient,
+    server: &str,
+    matches: &ArgMatches,
+    iterations: usize,
+    timeout: u64,
+) {
+    let follow_symlinks = matches.get_flag("follow-symlinks");
+    let mut files = Vec::new();
+    for root in matches.get_many::<String>("file").unwrap() {
+        let root = Path::new(root);
+        if root.is_dir() {
+            match collect_upload_files(root, follow_symlinks) {
+                Ok(found) => files.extend(found),
+                Err(e) => {
+                    tracing::error!("Error reading directory {}: {}", root.display(), e);
+                    std::process::exit(1);
+                }
+            }
+        } else {
+            let name = root.file_name().unwrap_or(root.as_os_str());
+            files.push((root.to_path_buf(), name.to_string_lossy().into_owned()));
+        }
+    }
+    if files.is_empty() {
+        tracing::warn!("No files to upload.");
+        return;
+    }
+
+    if let Some(checksum_file) = matches.get_one::<String>("check") {
+        verify_checksums(
+            checksum_file,
+            hash_algorithm(matches),
+            Verbosity::from_matches(matches),
+        );
+    }
+
+    let field_name = matches.get_one::<String>("field-name").unwrap();
+    let delete_path = matches.get_one::<String>("delete-path").unwrap();
+    let slow_threshold = slow_threshold(matches);
+    let mut csv_report = csv_report(client, matches);
+    let mut failures = Vec::new();
+    let mut uploaded = 0;
+    let mut total_bytes = 0;
+    let mut total_time = Duration::ZERO;
+    let mut pacer = OperationPacer::from_matches(matches);
+    let mut manifest = Manifest::new();
+    let upload_condition = UploadCondition::from_matches(matches);
+    let mut skipped = 0;
+    let mut stats = OperationStats::new("Uploads");
+    let run_start = Instant::now();
+
+    'iterations: for iteration in 1..=iterations {
+        let _span = tracing::info_span!("upload", iteration).entered();
+        // Listed once per iteration for --upload-if-changed
+        let mut listing = None;
+
+        for (path, remote_name) in &files {
+            if let Some(pacer) = pacer.as_mut() {
+                pacer.wait();
+            }
+            if interrupted() {
+                tracing::warn!("Stopped in iteration {} of {}", iteration, iterations);
+                break 'iterations;
+            }
+
+            if let Some(reason) = upload_condition.and_then(|condition| {
+                condition.skip_reason(client, server, path, remote_name, &mut listing)
+            }) {
+                tracing::info!("{}: Upload skipped, {}", remote_name, reason);
+                skipped += 1;
+                stats.succeeded(Duration::ZERO, 0);
+                if let Some(report) = csv_report.as_mut() {
+                    let mut row =
+                        CsvRow::new("upload", remote_name, "skipped".to_string(), Duration::ZERO);
+                    row.size_bytes = path.metadata().map(|m| m.len()).ok();
+                    report.write(row);
+                }
+                continue;
+            }
+
+            delete_before_upload(
+                client,
+                server,
+                delete_path,
+                remote_name,
+                matches.get_flag("encode-slash"),
+            );
+
+            let options = UploadOptions {
+                method: upload_method(matches),
+                timeout_secs: timeout,
+                field_name,
+                remote_name: Some(remote_name),
+                part_size: None,
+                content_type: matches
+                    .get_one::<String>("content-type")
+                    .map(String::as_str),
+                gzip: false,
+                path: matches.get_one::<String>("upload-path").map(String::as_str),
+                encode_slash: matches.get_flag("encode-slash"),
+                hash: hash_algorithm(matches),
+            };
+            let start_time = Instant::now();
+            let result = with_retries(client.retries(), || {
+                upload_file(client, server, path, &options)
+                    .and_then(|response| check_status(client, response).map_err(Into::into))
+            });
+            if let Some(report) = csv_report.as_mut() {
+                let mut row = CsvRow::new(
+                    "upload",
+                    remote_name,
+                    csv_status(result.as_ref().map(Response::status)),
+                    start_time.elapsed(),
+                );
+                row.size_bytes = path.metadata().map(|m| m.len()).ok();
+                report.write(row);
+            }
+            if let Some(write_out) = matches.get_one::<WriteOut>("write-out") {
+                let hash = write_out
+                    .uses(Variable::Hash)
+                    .then(|| hash_file(path, options.hash).ok())
+                    .flatten();
+                let values = WriteOutValues {
+                    operation: "upload",
+                    filename: remote_name,
+                    url: &upload_url(server, &options, remote_name),
+                    status: match &result {
+                        Ok(response) => Some(response.status().as_u16()),
+                        Err(e) => e.downcast_ref().and_then(DownloadError::status),
+                    },
+                    size: path.metadata().map(|m| m.len()).ok(),
+                    hash: hash.as_deref(),
+                    total: start_time.elapsed(),
+                    phases: &PhaseTimings {
+                        ttfb: result.as_ref().ok().and_then(http::response_wait),
+                        ..PhaseTimings::default()
+                    },
+                };
+                print_write_out(write_out, &values, false);
+            }
+            match result {
+                Ok(response) => {
+                    let duration = start_time.elapsed();
+                    let size = path.metadata().map_or(0, |m| m.len());
+                    uploaded += 1;
+                    total_bytes += size;
+                    total_time += duration;
+                    stats.succeeded(duration, size);
+                    tracing::info!(
+                        "{}: Uploaded. Status: {} Time taken: {:.2?}",
+                        remote_name,
+                        response.status().to_string().green(),
+                        duration
+                    );
+                    warn_if_slow(remote_name, duration, slow_threshold);
+                    if matches.contains_id("manifest") {
+                        add_to_manifest(&mut manifest, remote_name, path, options.hash);
+                    }
+                    client.dump_response(response);
+                }
+                Err(e) => {
+                    tracing::error!("Error uploading file {}: {}", path.display(), e);
+                    stats.failed();
+                    failures.push((path, e.to_string()));
+                }
+            }
+        }
+    }
+
+    tracing::info!(
+        "Uploaded {} files, {} in {:.2?} ({})",
+        uploaded,
+        human_size(total_bytes),
+        total_time,
+        human_throughput(total_bytes as f64 / total_time.as_secs_f64())
+    );
+    if upload_condition.is_some() {
+        tracing::info!("Skipped {} files the server already had", skipped);
+    }
+    stats.log(run_start.elapsed());
+    if let Some(pacer) = &pacer {
+        pacer.log_achieved("uploads");
+    }
+    if let Some(path) = matches.get_one::<String>("manifest") {
+        write_manifest(&manifest, path);
+    }
+
+    if !failures.is_empty() {
+        tracing::error!("{} uploads failed:", failures.len());
+        for (path, e) in &failures {
+            tracing::error!("  {}: {}", path.display(), e);
+        }
+        std::process::exit(1);
+    }
+    if interrupted() {
+        std::process::exit(130);
+    }
+}
+
+// Timestamps of log lines, local time unless --utc is given
+enum LogTimer {
+    Local(ChronoLocal),
+    Utc(ChronoUtc),
+}
+
+impl LogTimer {
+    fn from_matches(matches: &ArgMatches) -> Self {
+        let format = matches
+            .get_one::<String>("time-format")
+            .cloned()
+            .unwrap_or_else(|| DEFAULT_TIME_FORMAT.to_string());
+        if matches.get_flag("utc") {
+            LogTimer::Utc(ChronoUtc::new(format))
+        } else {
+            LogTimer::Local(ChronoLocal::new(format))
+        }
+    }
+}
+
+impl FormatTime for LogTimer {
+    fn format_time(&self, writer: &mut Writer<'_>) -> std::fmt::Result {
+        match self {
+            LogTimer::Local(timer) => timer.format_time(writer),
+            LogTimer::Utc(timer) => timer.format_time(writer),
+        }
+    }
+}
+
+// Log events go to stdout, warnings and errors to stderr, or everything to --log-file.
+// Everything goes to stderr while a download is written to stdout.
+fn init_logging(matches: &ArgMatches) -> io::Result<()> {
+    let crate_name = env!("CARGO_CRATE_NAME");
+    let level = match matches.get_one::<String>("log-level") {
+        Some(level) => level.parse().unwrap_or(tracing::Level::INFO),
+        None if matches.get_count("quiet") > 0 => tracing::Level::ERROR,
+        None => match matches.get_count("verbose") {
+            0 => tracing::Level::INFO,
+            1 => tracing::Level::DEBUG,
+            _ => tracing::Level::TRACE,
+        },
+    };
+    let directives = match level {
+        // Also trace the HTTP stack
+        tracing::Level::TRACE => "trace".to_string(),
+        tracing::Level::DEBUG | tracing::Level::INFO => {
+            format!(
+                "warn,{}={}",
+                crate_name,
+                level.as_str().to_ascii_lowercase()
+            )
+        }
+        _ => level.as_str().to_ascii_lowercase(),
+    };
+
+    // RUST_LOG takes precedence over the verbosity flags when set, an explicit --log-level wins over both
+    let filter = match EnvFilter::try_from_default_env() {
+        Ok(filter) if !matches.contains_id("log-level") => filter,
+        _ => EnvFilter::new(directives),
+    };
+
+    let (writer, ansi) = match matches.get_one::<String>("log-file") {
+        Some(log_file) => {
+            let file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(log_file)?;
+            // Errors are still shown on the terminal
+            let writer = Arc::new(file).and(io::stderr.with_max_level(tracing::Level::ERROR));
+            (BoxMakeWriter::new(writer), false)
+        }
+        None if stdout_is_data(matches) => {
+            (BoxMakeWriter::new(io::stderr), output::color_enabled())
+        }
+        None => (
+            BoxMakeWriter::new(
+                io::stderr
+                    .with_max_level(tracing::Level::WARN)
+                    .or_else(io::stdout),
+            ),
+            output::color_enabled(),
+        ),
+    };
+
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_target(false)
+        .with_ansi(ansi)
+        .fmt_fields(output::log_fields(ansi))
+        .with_timer(LogTimer::from_matches(matches))
+        .with_writer(writer)
+        .init();
+    Ok(())
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let matches = cli::build_cli().get_matches();
+    let json = matches
+        .subcommand()
+        .and_then(|(_, sub)| sub.try_get_one::<bool>("json").ok().flatten())
+        .copied()
+        .unwrap_or(false);
+    // Log files never receive colour codes unless they are explicitly requested
+    let color = match matches.get_one::<String>("color").map(String::as_str) {
+        Some("auto") if matches.contains_id("log-file") => "never",
+        Some(color) => color,
+        None => "auto",
+    };
+    output::init_color(color, json);
+    init_logging(&matches)?;
+    install_interrupt_handler();
+
+    let mut config = match Config::load(matches.get_one::<String>("config").map(Path::new)) {
+        Ok(config) => config,
+        Err(e) => {
+            tracing::error!("Error loading config file {}", e);
+            std::process::exit(1);
+        }
+    };
+    if let Some(profile) = matches.get_one::<String>("profile") {
+        if let Err(e) = config.select_profile(profile) {
+            tracing::error!("Error selecting profile: {}", e);
+            std::process::exit(1);
+        }
+    }
+
+    // --server is validated while parsing, a server from the config file only when it is used
+    if let (None, Some(server)) = (matches.get_one::<String>("server"), config.server.as_mut()) {
+        match cli::parse_server_url(server) {
+            Ok(url) => *server = url,
+            Err(e) => {
+                tracing::error!("Invalid server URL in the config file: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+    let server_url = matches
+        .get_one::<String>("server")
+        .or(config.server.as_ref());
+
+    let iterations = matches
+        .get_one::<String>("iterations")
+        .and_then(|it| it.parse::<usize>().ok())
+        .unwrap_or(1);
+
+    let timeout = request_timeout(&matches, &config);
+
+    match matches.subcommand() {
+        Some(("generate", sub)) => run_generate(sub)?,
+        Some(("check", sub)) => match sub.get_many::<String>("sidecar") {
+            Some(files) => {
+                verify_sidecars(files, hash_algorithm(sub), Verbosity::from_matches(sub))
+            }
+            None => verify_checksums(
+                sub.get_one::<String>("checksum-file").unwrap(),
+                hash_algorithm(sub),
+                Verbosity::from_matches(sub),
+            ),
+        },
+        Some(("list", sub)) => {
+            let server = require_server(server_url, "listing files");
+            run_list(&client_from_matches(&matches, &config)?, server, sub)?;
+        }
+        Some(("exists", sub)) => {
+            let server = require_server(server_url, "checking files");
+            run_exists(&client_from_matches(&matches, &config)?, server, sub);
+        }
+        Some(("delete", sub)) => {
+            let server = require_server(server_url, "deleting files");
+            run_delete(&client_from_matches(&matches, &config)?, server, sub)?;
+        }
+        Some(("upload", sub)) if sub.get_flag("recursive") && !sub.get_flag("async") => {
+            let server = require_server(server_url, "uploading files");
+            let client = client_from_matches(&matches, &config)?;
+            run_recursive_upload(&client, server, sub, iterations, timeout);
+        }
+        Some((command @ ("upload" | "download" | "roundtrip"), sub)) => {
+            let server = match command {
+                "upload" => require_server(server_url, "uploading files"),
+                _ => require_server(server_url, "downloading files"),
+            };
+            let client = client_from_matches(&matches, &config)?;
+            if sub.get_flag("async") {
+                let upload = command != "download";
+                let download = command != "upload";
+                run_async(client, server, sub, iterations, timeout, upload, download);
+                return Ok(());
+            }
+            run_transfers(
+                &client,
+                server,
+                sub,
+                iterations,
+                timeout,
+                command != "download",
+                command != "upload",
+            );
+        }
+        Some(("completions", sub)) => {
+            let shell = *sub.get_one::<clap_complete::Shell>("shell").unwrap();
+            clap_complete::generate(
+                shell,
+                &mut cli::build_cli(),
+                env!("CARGO_PKG_NAME"),
+                &mut io::stdout(),
+            );
+        }
+        _ => println!("No command provided. Use --help for usage information."),
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempPath;
+
+    #[test]
+    fn seeded_generation_is_reproducible() {
+        let path = TempPath::new("seeded-generation.txt");
+
+        let options = GenerateOptions {
+            seed: 42,
+            content: ContentKind::Text,
+            compressibility: 0,
+            force: false,
+            progress: ProgressBar::hidden(),
+            block_size: GENERATE_BLOCK_SIZE,
+            hash: HashAlgorithm::Sha256,
+            hash_threads: 1,
+        };
+        let hash = generate_random_text_file(&path, 4096, &options).unwrap();
+        let content = std::fs::read(&path).unwrap();
+
+        assert_eq!(
+            hash,
+            "8956dbc974ce9ab6dc9cf878345995e557ce75a05357c4633a1d43e127939448"
+        );
+        assert!(content.iter().all(u8::is_ascii_alphanumeric));
+    }
+
+    #[test]
+    fn hash_algorithms() {
+        let path = TempPath::new("hash.txt");
+        std::fs::write(&path, b"abc").unwrap();
+        let hash = |algorithm| hash_file(&path, algorithm).unwrap();
+        assert_eq!(
+            hash(HashAlgorithm::Sha256),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            hash(HashAlgorithm::Sha512),
+            "ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a\
+             2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f"
+        );
+        assert_eq!(
+            hash(HashAlgorithm::Blake3),
+            "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85"
+        );
+        assert_eq!(hash(HashAlgorithm::None), "-");
+    }
+
+    #[test]
+    fn parallel_blake3_matches_sequential() {
+        let path = TempPath::new("parallel.bin");
+        // Not a multiple of the chunk size, so the last subtree is partial
+        let content: Vec<u8> = (0..5 * 1024 * 1024 + 123)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        std::fs::write(&path, &content).unwrap();
+        let sequential = hash_file(&path, HashAlgorithm::Blake3).unwrap();
+        for threads in [1, 2, 3, 8] {
+            assert_eq!(
+                hash_file_on(&path, HashAlgorithm::Blake3, threads).unwrap(),
+                sequential
+            );
+        }
+    }
+
+    #[test]
+    fn hash_sidecar_round_trip() {
+        let path = TempPath::new("sidecar.bin");
+        let sidecar = TempPath::new("sidecar.bin.sha256");
+        std::fs::write(&path, b"abc").unwrap();
+        let hash = hash_file(&path, HashAlgorithm::Sha256).unwrap();
+        write_hash_sidecar(&path, HashAlgorithm::Sha256, &hash).unwrap();
+
+        assert_eq!(
+            sidecar_path(&path, HashAlgorithm::Sha256).unwrap(),
+            *sidecar
+        );
+        let content = std::fs::read_to_string(&sidecar).unwrap();
+        let name = path.file_name().unwrap().to_string_lossy();
+        assert_eq!(content, format!("{}  {}\n", hash, name));
+        assert!(read_and_verify_sidecar(&path, HashAlgorithm::Sha256).unwrap());
+
+        std::fs::write(&path, b"abd").unwrap();
+        assert!(!read_and_verify_sidecar(&path, HashAlgorithm::Sha256).unwrap());
+    }
+
+    #[test]
+    fn template_expansion() {
+        assert_eq!(expand_template("data-{n}.bin", 7), "data-7.bin");
+        assert_eq!(expand_template("data-{n:03}.bin", 7), "data-007.bin");
+        assert_eq!(expand_template("{n}/{n:02}", 3), "3/03");
+        assert_eq!(expand_template("plain.bin", 7), "plain.bin");
+    }
+
+    #[test]
+    fn upload_method_ignores_case() {
+        for (method, expected) in [
+            ("put", UploadMethod::Put),
+            ("PUT", UploadMethod::Put),
+            ("Post", UploadMethod::Post),
+        ] {
+            let matches = cli::build_cli().get_matches_from([
+                "simple-file-client",
+                "upload",
+                "a.bin",
+                "--method",
+                method,
+            ]);
+            let (_, sub) = matches.subcommand().unwrap();
+            assert_eq!(upload_method(sub), expected);
+        }
+    }
+
+    #[test]
+    fn upload_url_templates() {
+        let mut options = UploadOptions {
+            method: UploadMethod::Post,
+            timeout_secs: 30,
+            field_name: "file",
+            remote_name: None,
+            part_size: None,
+            content_type: None,
+            gzip: false,
+            path: None,
+            encode_slash: false,
+            hash: HashAlgorithm::Sha256,
+        };
+        let server = "http://localhost:8080";
+        assert_eq!(
+            upload_url(server, &options, "a.bin"),
+            "http://localhost:8080/upload"
+        );
+        options.method = UploadMethod::Put;
+        assert_eq!(
+            upload_url(server, &options, "a.bin"),
+            "http://localhost:8080/a.bin"
+        );
+        options.path = Some("/files/{name}");
+        assert_eq!(
+            upload_url(server, &options, "a.bin"),
+            "http://localhost:8080/files/a.bin"
+        );
+    }
+
+    #[test]
+    fn endpoint_url_templates() {
+        for server in ["http://localhost:8080", "http://localhost:8080/"] {
+            assert_eq!(
+                endpoint_url(server, "/download/{name}", "a.bin", false),
+                "http://localhost:8080/download/a.bin"
+            );
+            assert_eq!(
+                endpoint_url(server, "api/v1/files/{file}", "a.bin", false),
+                "http://localhost:8080/api/v1/files/a.bin"
+            );
+            // A trailing slash appends the name, without one the path is used as is
+            assert_eq!(
+                endpoint_url(server, "/api/v1/files/", "a.bin", false),
+                "http://localhost:8080/api/v1/files/a.bin"
+            );
+            assert_eq!(
+                endpoint_url(server, "/upload", "a.bin", false),
+                "http://localhost:8080/upload"
+            );
+            assert_eq!(
+                endpoint_url(server, "/files/{name}/", "a.bin", false),
+                "http://localhost:8080/files/a.bin/"
+            );
+        }
+        assert_eq!(
+            endpoint_url("http://h", "/{name}", "my file #1?.bin", false),
+            "http://h/my%20file%20%231%3F.bin"
+        );
+        assert_eq!(
+            endpoint_url("http://h", "/{name}", "100%.bin", false),
+            "http://h/100%25.bin"
+        );
+        assert_eq!(
+            endpoint_url("http://h", "/{name}", "dir/ä.bin", false),
+            "http://h/dir/%C3%A4.bin"
+        );
+        assert_eq!(
+            endpoint_url("http://h", "/{name}", "dir/ä.bin", true),
+            "http://h/dir%2F%C3%A4.bin"
+        );
+    }
+
+    #[test]
+    fn tricky_names_reach_the_server_encoded() {
+        let names = [
+            ("my report (v2).txt", "/download/my%20report%20(v2).txt"),
+            ("a#b?c.bin", "/download/a%23b%3Fc.bin"),
+            ("50%.bin", "/download/50%25.bin"),
+            ("grüße €.bin", "/download/gr%C3%BC%C3%9Fe%20%E2%82%AC.bin"),
+            ("dir/a b.bin", "/download/dir/a%20b.bin"),
+        ];
+        assert_eq!(encode_path("dir/a b.bin", true), "dir%2Fa%20b.bin");
+
+        // A mock server that answers every request with its request target
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = format!("http://{}", listener.local_addr().unwrap());
+        let mock = std::thread::spawn(move || {
+            for _ in 0..names.len() * 2 {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request_line = String::new();
+                BufReader::new(&stream)
+                    .read_line(&mut request_line)
+                    .unwrap();
+                let target = request_line
+                    .split(' ')
+                    .nth(1)
+                    .unwrap_or_default()
+                    .to_string();
+                write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    target.len(),
+                    target
+                )
+                .unwrap();
+            }
+        });
+        let client = reqwest::blocking::Client::builder()
+            .no_proxy()
+            .build()
+            .unwrap();
+        for (name, path) in names {
+            let url = endpoint_url(&server, "/download/{name}", name, false);
+            assert_eq!(url, format!("{}{}", server, path));
+            assert_eq!(client.get(&url).send().unwrap().text().unwrap(), path);
+            // Parts of chunked uploads are named the same way
+            let path = path.replacen("/download/", "/upload-chunked/", 1);
+            let url = endpoint_url(&server, CHUNKED_UPLOAD_PATH, name, false);
+            assert_eq!(client.post(&url).send().unwrap().text().unwrap(), path);
+        }
+        mock.join().unwrap();
+    }
+
+    #[test]
+    fn output_paths_cannot_escape_target_directory() {
+        assert_eq!(
+            sanitize_relative_path("subdir/report.csv").unwrap(),
+            Path::new("subdir").join("report.csv")
+        );
+        assert_eq!(
+            sanitize_relative_path("/./a//b").unwrap(),
+            Path::new("a").join("b")
+        );
+        assert!(sanitize_relative_path("../etc/passwd").is_err());
+        assert!(sanitize_relative_path("a/../../b").is_err());
+        assert!(sanitize_relative_path("a\\..\\b").is_err());
+        assert!(sanitize_relative_path("").is_err());
+    }
+
+    #[test]
+    fn human_readable_sizes() {
+        let sizes = [
+            (0, "0 B"),
+            (1023, "1023 B"),
+            (1024, "1.00 KiB"),
+            (1260, "1.23 KiB"),
+            (47_815_066, "45.6 MiB"),
+            (104_857_600, "100 MiB"),
+            (1_048_575, "1.00 MiB"),
+            (1_148_903_751, "1.07 GiB"),
+            (u64::MAX, "16.0 EiB"),
+        ];
+        for (bytes, expected) in sizes {
+            assert_eq!(human_size(bytes), expected, "{} bytes", bytes);
+        }
+
+        let rates = [
+            (0.0, "0.00 B/s"),
+            (12.34, "12.3 B/s"),
+            (512.0, "512 B/s"),
+            (1536.0, "1.50 KiB/s"),
+            (10_485_760.0, "10.0 MiB/s"),
+            (f64::INFINITY, "0.00 B/s"),
+        ];
+        for (rate, expected) in rates {
+            assert_eq!(human_throughput(rate), expected, "{} B/s", rate);
+        }
+    }
+
+    #[test]
+    fn content_range_parsing() {
+        let range = |value: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(
+                reqwest::header::CONTENT_RANGE,
+                HeaderValue::from_str(value).unwrap(),
+            );
+            content_range(&headers)
+        };
+        assert_eq!(range("bytes 100-199/200"), (Some(100), Some(200)));
+        assert_eq!(range("bytes 100-199/*"), (Some(100), None));
+        assert_eq!(range("bytes */200"), (None, Some(200)));
+        assert_eq!(range("items 0-1/2"), (None, None));
+        assert_eq!(content_range(&HeaderMap::new()), (None, None));
+    }
+
+    #[test]
+    fn download_errors_are_classified() {
+        // Nothing listens on the discard port of the loopback interface
+        let e = reqwest::blocking::get("http://127.0.0.1:9/").unwrap_err();
+        assert!(matches!(DownloadError::from(e), DownloadError::Connect(_)));
+
+        // As returned by a `Read` of the response
+        let wrapped = io::Error::other(reqwest::blocking::get("http://127.0.0.1:9/").unwrap_err());
+        assert!(matches!(
+            DownloadError::from(wrapped),
+            DownloadError::Connect(_)
+        ));
+
+        let io_error = io::Error::new(io::ErrorKind::NotFound, "missing");
+        assert!(matches!(
+            DownloadError::from(io_error),
+            DownloadError::Io(_)
+        ));
+
+        assert_eq!(
+            DownloadError::Status(404).to_string(),
+            "Unexpected status: 404 Not Found"
+        );
+        let redirected = DownloadError::Redirected {
+            status: 301,
+            location: "https://files.example.com/upload".to_string(),
+        };
+        assert_eq!(
+            redirected.to_string(),
+            "Redirected (301 Moved Permanently) to https://files.example.com/upload"
+        );
+        let mismatch = DownloadError::HashMismatch {
+            expected: "abcd".to_string(),
+            actual: "ef01".to_string(),
+        };
+        assert_eq!(
+            mismatch.to_string(),
+            "Hash verification: FAILED (expected abcd, got ef01)"
+        );
+    }
+
+    fn gzip_size(path: &Path) -> usize {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&std::fs::read(path).unwrap()).unwrap();
+        encoder.finish().unwrap().len()
+    }
+
+    #[test]
+    fn compressibility_controls_gzip_ratio() {
+        let mut sizes = Vec::new();
+
+        for compressibility in [10, 90] {
+            let path = TempPath::new(&format!("compressibility-{}.bin", compressibility));
+
+            let options = GenerateOptions {
+                seed: 7,
+                content: ContentKind::Binary,
+                compressibility,
+                force: false,
+                progress: ProgressBar::hidden(),
+                block_size: GENERATE_BLOCK_SIZE,
+                hash: HashAlgorithm::Sha256,
+                hash_threads: 1,
+            };
+            generate_random_text_file(&path, 256 * 1024, &options).unwrap();
+            sizes.push(gzip_size(&path));
+        }
+
+        assert!(sizes[1] * 4 < sizes[0], "gzip sizes: {:?}", sizes);
+    }
 }
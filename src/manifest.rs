@@ -0,0 +1,141 @@
+use crate::hash::HashAlgorithm;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::Path;
+
+// Current version of the manifest format, readers reject manifests of a newer version
+const MANIFEST_VERSION: u32 = 1;
+
+// JSON manifest of uploaded files that later downloads are verified against. Unknown fields are
+// ignored when reading, so fields can be added without a new version.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Manifest {
+    pub version: u32,
+    pub files: Vec<ManifestEntry>,
+}
+
+// A file under its remote name, with the hash of the algorithm it was uploaded with
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct ManifestEntry {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha512: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blake3: Option<String>,
+    pub size: u64,
+}
+
+impl ManifestEntry {
+    pub fn new(name: &str, algorithm: HashAlgorithm, hash: &str, size: u64) -> Self {
+        let mut entry = Self {
+            name: name.to_string(),
+            sha256: None,
+            sha512: None,
+            blake3: None,
+            size,
+        };
+        if let Some(slot) = entry.hash_slot(algorithm) {
+            *slot = Some(hash.to_string());
+        }
+        entry
+    }
+
+    pub fn hash(&self, algorithm: HashAlgorithm) -> Option<&str> {
+        match algorithm {
+            HashAlgorithm::Sha256 => self.sha256.as_deref(),
+            HashAlgorithm::Sha512 => self.sha512.as_deref(),
+            HashAlgorithm::Blake3 => self.blake3.as_deref(),
+            HashAlgorithm::None => None,
+        }
+    }
+
+    fn hash_slot(&mut self, algorithm: HashAlgorithm) -> Option<&mut Option<String>> {
+        match algorithm {
+            HashAlgorithm::Sha256 => Some(&mut self.sha256),
+            HashAlgorithm::Sha512 => Some(&mut self.sha512),
+            HashAlgorithm::Blake3 => Some(&mut self.blake3),
+            HashAlgorithm::None => None,
+        }
+    }
+}
+
+impl Manifest {
+    pub fn new() -> Self {
+        Self {
+            version: MANIFEST_VERSION,
+            files: Vec::new(),
+        }
+    }
+
+    pub fn find(&self, name: &str) -> Option<&ManifestEntry> {
+        self.files.iter().find(|entry| entry.name == name)
+    }
+
+    // Adds a file, replacing an earlier entry of the same name
+    pub fn add(&mut self, entry: ManifestEntry) {
+        self.files.retain(|existing| existing.name != entry.name);
+        self.files.push(entry);
+    }
+
+    pub fn read(path: &Path) -> io::Result<Self> {
+        let manifest: Manifest = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+        if manifest.version > MANIFEST_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Unsupported manifest version {}, at most {} is supported",
+                    manifest.version, MANIFEST_VERSION
+                ),
+            ));
+        }
+        Ok(manifest)
+    }
+
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writeln!(writer)?;
+        writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempPath;
+
+    #[test]
+    fn manifest_round_trip() {
+        let path = TempPath::new("manifest.json");
+        let mut manifest = Manifest::new();
+        manifest.add(ManifestEntry::new("a.bin", HashAlgorithm::Sha256, "aa", 1));
+        manifest.add(ManifestEntry::new("a.bin", HashAlgorithm::Sha256, "ab", 2));
+        manifest.add(ManifestEntry::new("b.bin", HashAlgorithm::Blake3, "bb", 3));
+        manifest.write(&path).unwrap();
+
+        let read = Manifest::read(&path).unwrap();
+        assert_eq!(read, manifest);
+        assert_eq!(read.files.len(), 2);
+        assert_eq!(
+            read.find("a.bin").unwrap().hash(HashAlgorithm::Sha256),
+            Some("ab")
+        );
+        assert_eq!(
+            read.find("b.bin").unwrap().hash(HashAlgorithm::Sha256),
+            None
+        );
+
+        // Unknown fields are ignored, newer versions are rejected
+        std::fs::write(
+            &path,
+            r#"{"version":1,"files":[{"name":"c","sha256":"cc","size":4,"mtime":5}]}"#,
+        )
+        .unwrap();
+        assert!(Manifest::read(&path).unwrap().find("c").is_some());
+        std::fs::write(&path, r#"{"version":2,"files":[]}"#).unwrap();
+        assert!(Manifest::read(&path).is_err());
+    }
+}
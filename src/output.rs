@@ -0,0 +1,111 @@
+// Formatting helpers for values shown to the user
+
+use std::io::{self, IsTerminal};
+
+// strftime format of log timestamps
+pub const DEFAULT_TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S%.6f %:z";
+
+const IEC_UNITS: [&str; 7] = ["B", "KiB", "MiB", "GiB", "TiB", "PiB", "EiB"];
+
+// Formats a byte count with IEC units rounded to three significant figures, e.g. "1.23 KiB"
+pub fn human_size(bytes: u64) -> String {
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    scale(bytes as f64, "")
+}
+
+// Formats a transfer rate like `human_size`, e.g. "45.6 MiB/s"
+pub fn human_throughput(bytes_per_sec: f64) -> String {
+    let bytes_per_sec = if bytes_per_sec.is_finite() {
+        bytes_per_sec.max(0.0)
+    } else {
+        0.0
+    };
+    scale(bytes_per_sec, "/s")
+}
+
+fn scale(mut value: f64, suffix: &str) -> String {
+    let mut unit = 0;
+    while value >= 1024.0 && unit < IEC_UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    // 1023.6 KiB would round to "1024 KiB", show it as "1.00 MiB" instead
+    if value >= 1023.5 && unit < IEC_UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    let decimals = match value {
+        v if v >= 99.95 => 0,
+        v if v >= 9.995 => 1,
+        _ => 2,
+    };
+    format!("{:.*} {}{}", decimals, value, IEC_UNITS[unit], suffix)
+}
+
+// Applies --color, "auto" only colours output when both stdout and stderr are terminals.
+// JSON output is never coloured.
+pub fn init_color(choice: &str, json: bool) {
+    let enabled = match choice {
+        "always" => true,
+        "never" => false,
+        _ => {
+            io::stdout().is_terminal()
+                && io::stderr().is_terminal()
+                && std::env::var_os("NO_COLOR").is_none()
+        }
+    };
+    colored::control::set_override(enabled && !json);
+}
+
+pub fn color_enabled() -> bool {
+    colored::control::SHOULD_COLORIZE.should_colorize()
+}
+
+// Writes log fields like the default formatter, which escapes every escape sequence and so the
+// colours of `colored` as well. Colour (SGR) sequences are kept when `keep_colors` is set, any
+// other control character is escaped, so text from a server cannot move the cursor or retitle
+// the terminal.
+pub fn log_fields(
+    keep_colors: bool,
+) -> impl for<'w> tracing_subscriber::fmt::FormatFields<'w> + 'static {
+    use tracing_subscriber::field::MakeExt;
+    tracing_subscriber::fmt::format::debug_fn(move |writer, field, value| {
+        let text = format!("{:?}", value);
+        if field.name() != "message" {
+            write!(writer, "{}=", field.name())?;
+        }
+        write_escaped(writer, &text, keep_colors)
+    })
+    .delimited(" ")
+}
+
+fn write_escaped(
+    writer: &mut impl std::fmt::Write,
+    text: &str,
+    keep_colors: bool,
+) -> std::fmt::Result {
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        let sgr_len = rest
+            .strip_prefix("\x1b[")
+            .and_then(|params| params.find(|c: char| !c.is_ascii_digit() && c != ';'))
+            .filter(|&end| rest[2 + end..].starts_with('m'))
+            .map(|end| end + 3);
+        match sgr_len {
+            Some(len) if keep_colors => {
+                writer.write_str(&rest[..len])?;
+                rest = &rest[len..];
+                continue;
+            }
+            _ if c.is_control() && c != '\n' && c != '\t' => {
+                write!(writer, "{}", c.escape_default())?
+            }
+            _ => writer.write_char(c)?,
+        }
+        rest = &rest[c.len_utf8()..];
+    }
+    Ok(())
+}
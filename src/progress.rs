@@ -0,0 +1,56 @@
+use indicatif::{ProgressBar, ProgressStyle};
+use std::io::{self, Read};
+
+// Progress bar for a transfer of `len` bytes, or a spinner when the length is unknown.
+// Disabled bars are hidden, indicatif also hides them when stderr is not a terminal.
+pub fn transfer_bar(len: Option<u64>, enabled: bool) -> ProgressBar {
+    if !enabled {
+        return ProgressBar::hidden();
+    }
+    match len {
+        Some(len) => ProgressBar::new(len).with_style(
+            ProgressStyle::with_template(
+                "[{elapsed_precise}] [{bar:40}] {bytes}/{total_bytes} ({bytes_per_sec}, {eta})",
+            )
+            .unwrap()
+            .progress_chars("=> "),
+        ),
+        None => ProgressBar::new_spinner().with_style(
+            ProgressStyle::with_template("[{elapsed_precise}] {spinner} {bytes} ({bytes_per_sec})")
+                .unwrap(),
+        ),
+    }
+}
+
+// Completes the bar on success, leaves it where the transfer stopped otherwise
+pub fn finish_bar<T, E>(bar: &ProgressBar, result: &Result<T, E>) {
+    if result.is_ok() {
+        bar.finish();
+    } else {
+        bar.abandon();
+    }
+    // The cursor stays on the bar line, log output must start on a new one
+    if !bar.is_hidden() {
+        eprintln!();
+    }
+}
+
+// Reader that advances a progress bar by the bytes read through it
+pub struct ProgressReader<R> {
+    inner: R,
+    bar: ProgressBar,
+}
+
+impl<R: Read> ProgressReader<R> {
+    pub fn new(inner: R, bar: ProgressBar) -> Self {
+        Self { inner, bar }
+    }
+}
+
+impl<R: Read> Read for ProgressReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.bar.inc(read as u64);
+        Ok(read)
+    }
+}
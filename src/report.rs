@@ -0,0 +1,84 @@
+use crate::timing::{millis, PhaseTimings};
+use serde::Serialize;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+
+// One row of the --csv report, written once an upload or download has finished
+#[derive(Serialize)]
+pub struct CsvRow<'a> {
+    pub timestamp: String,
+    pub operation: &'a str,
+    pub filename: &'a str,
+    pub size_bytes: Option<u64>,
+    // HTTP status code, or "error" when no response was received
+    pub status: String,
+    pub duration_ms: f64,
+    pub sha256: Option<&'a str>,
+    // "keepalive" when connections are reused, "close" with --no-keepalive
+    pub connection: &'static str,
+    // Connection setup phases are only measured with --timing
+    pub dns_ms: Option<f64>,
+    pub connect_ms: Option<f64>,
+    pub tls_ms: Option<f64>,
+    pub ttfb_ms: Option<f64>,
+    pub transfer_ms: Option<f64>,
+}
+
+impl<'a> CsvRow<'a> {
+    pub fn new(operation: &'a str, filename: &'a str, status: String, duration: Duration) -> Self {
+        Self {
+            timestamp: chrono::Local::now().to_rfc3339(),
+            operation,
+            filename,
+            size_bytes: None,
+            status,
+            duration_ms: millis(Some(duration)).unwrap_or_default(),
+            sha256: None,
+            connection: "keepalive",
+            dns_ms: None,
+            connect_ms: None,
+            tls_ms: None,
+            ttfb_ms: None,
+            transfer_ms: None,
+        }
+    }
+
+    pub fn set_phases(&mut self, phases: &PhaseTimings) {
+        self.dns_ms = millis(phases.dns);
+        self.connect_ms = millis(phases.connect);
+        self.tls_ms = millis(phases.tls);
+        self.ttfb_ms = millis(phases.ttfb);
+        self.transfer_ms = millis(phases.transfer);
+    }
+}
+
+// Timing data with one row per operation, for analysis in a spreadsheet
+pub struct CsvReport {
+    writer: csv::Writer<File>,
+    // Connection mode recorded in every row, so runs with and without keep-alive are not mixed up
+    connection: &'static str,
+}
+
+impl CsvReport {
+    pub fn create(path: &Path, keepalive: bool) -> io::Result<Self> {
+        Ok(Self {
+            writer: csv::Writer::from_writer(File::create(path)?),
+            connection: if keepalive { "keepalive" } else { "close" },
+        })
+    }
+
+    // Rows are flushed one by one so a crashed run still leaves the rows written so far
+    pub fn write(&mut self, mut row: CsvRow) {
+        row.connection = self.connection;
+        let result = self
+            .writer
+            .serialize(row)
+            .map_err(io::Error::from)
+            .and_then(|_| self.writer.flush());
+        if let Err(e) = result {
+            tracing::warn!("Error writing CSV report: {}", e);
+        }
+    }
+}
@@ -0,0 +1,66 @@
+use crate::output::{human_size, human_throughput};
+use std::time::Duration;
+
+// Tally of one kind of operation over a run, logged as the summary at its end
+pub struct OperationStats {
+    operation: &'static str,
+    durations: Vec<Duration>,
+    bytes: u64,
+    failed: usize,
+}
+
+impl OperationStats {
+    pub fn new(operation: &'static str) -> Self {
+        Self {
+            operation,
+            durations: Vec::new(),
+            bytes: 0,
+            failed: 0,
+        }
+    }
+
+    pub fn succeeded(&mut self, duration: Duration, bytes: u64) {
+        self.durations.push(duration);
+        self.bytes += bytes;
+    }
+
+    pub fn failed(&mut self) {
+        self.failed += 1;
+    }
+
+    pub fn failures(&self) -> usize {
+        self.failed
+    }
+
+    // Nothing is logged for an operation that was never attempted
+    pub fn log(&mut self, elapsed: Duration) {
+        let completed = self.durations.len();
+        let attempted = completed + self.failed;
+        if attempted == 0 {
+            return;
+        }
+        tracing::info!(
+            "{}: {} attempted, {} succeeded, {} failed ({:.1}% errors), {:.1}/s, {} ({})",
+            self.operation,
+            attempted,
+            completed,
+            self.failed,
+            self.failed as f64 * 100.0 / attempted as f64,
+            completed as f64 / elapsed.as_secs_f64(),
+            human_size(self.bytes),
+            human_throughput(self.bytes as f64 / elapsed.as_secs_f64())
+        );
+        if completed > 0 {
+            self.durations.sort();
+            let percentile = |p: f64| self.durations[((completed - 1) as f64 * p).round() as usize];
+            tracing::info!(
+                "{}: latency average {:.2?}, p50 {:.2?}, p99 {:.2?}, max {:.2?}",
+                self.operation,
+                self.durations.iter().sum::<Duration>() / completed as u32,
+                percentile(0.5),
+                percentile(0.99),
+                self.durations[completed - 1]
+            );
+        }
+    }
+}
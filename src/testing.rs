@@ -0,0 +1,48 @@
+// Helpers shared by the unit tests of the modules
+
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+// A file or directory below the system temp directory that is removed when the guard is dropped,
+// also when an assert fails. The name is made unique per test process.
+pub struct TempPath(PathBuf);
+
+impl TempPath {
+    pub fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!(
+            "simple-file-client-{}-{}",
+            std::process::id(),
+            name
+        ));
+        remove(&path);
+        TempPath(path)
+    }
+}
+
+impl Deref for TempPath {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TempPath {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        remove(&self.0);
+    }
+}
+
+fn remove(path: &Path) {
+    if path.is_dir() {
+        let _ = std::fs::remove_dir_all(path);
+    } else {
+        let _ = std::fs::remove_file(path);
+    }
+}
@@ -0,0 +1,135 @@
+use std::collections::VecDeque;
+use std::io::{self, Read};
+use std::time::{Duration, Instant};
+
+// Token bucket refilled at a fixed byte rate, a burst is capped at 50 ms of traffic to keep the rate smooth
+pub struct TokenBucket {
+    rate: f64,
+    capacity: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(bytes_per_sec: u64) -> Self {
+        let rate = bytes_per_sec.max(1) as f64;
+        let capacity = (rate / 20.0).max(1.0);
+        Self {
+            rate,
+            capacity,
+            tokens: capacity,
+            last_refill: Instant::now(),
+        }
+    }
+
+    // Paces single operations evenly without bursts, an operation that starts late is not made up for
+    pub fn pacing(per_sec: u64) -> Self {
+        Self {
+            rate: per_sec.max(1) as f64,
+            capacity: 1.0,
+            tokens: 1.0,
+            last_refill: Instant::now(),
+        }
+    }
+
+    // Largest amount a single `take` is meant for, bigger transfers should be split
+    pub fn capacity(&self) -> usize {
+        self.capacity as usize
+    }
+
+    // Blocks until `amount` bytes may be transferred without exceeding the rate
+    pub fn take(&mut self, amount: usize) {
+        self.refill();
+        let amount = amount as f64;
+        if self.tokens < amount {
+            std::thread::sleep(Duration::from_secs_f64((amount - self.tokens) / self.rate));
+            self.refill();
+        }
+        self.tokens -= amount;
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.last_refill = now;
+    }
+}
+
+// Reader that sleeps between reads to stay under a byte rate
+pub struct ThrottledReader<R> {
+    inner: R,
+    bucket: TokenBucket,
+}
+
+impl<R: Read> ThrottledReader<R> {
+    pub fn new(inner: R, bytes_per_sec: u64) -> Self {
+        Self {
+            inner,
+            bucket: TokenBucket::new(bytes_per_sec),
+        }
+    }
+}
+
+impl<R: Read> Read for ThrottledReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = buf.len().min(self.bucket.capacity());
+        let read = self.inner.read(&mut buf[..len])?;
+        self.bucket.take(read);
+        Ok(read)
+    }
+}
+
+const SPEED_WINDOW: Duration = Duration::from_secs(5);
+
+// Tracks the average speed over a rolling window and reports when it stayed below a minimum for
+// too long, like curl's --speed-limit/--speed-time. Only checked as data arrives, a connection that
+// delivers nothing at all is left to the request timeout.
+pub struct SpeedMonitor {
+    required: u64,
+    timeout: Duration,
+    start: Instant,
+    window: VecDeque<(Instant, u64)>,
+    window_bytes: u64,
+    below_since: Option<Instant>,
+}
+
+impl SpeedMonitor {
+    pub fn new(required_bytes_per_sec: u64, timeout: Duration) -> Self {
+        Self {
+            required: required_bytes_per_sec,
+            timeout,
+            start: Instant::now(),
+            window: VecDeque::new(),
+            window_bytes: 0,
+            below_since: None,
+        }
+    }
+
+    // Records received bytes, returns the measured speed once it has been too low for longer than the timeout
+    pub fn record(&mut self, bytes: u64) -> Result<(), u64> {
+        let now = Instant::now();
+        self.window.push_back((now, bytes));
+        self.window_bytes += bytes;
+        while let Some(&(time, bytes)) = self.window.front() {
+            if now.duration_since(time) <= SPEED_WINDOW {
+                break;
+            }
+            self.window_bytes -= bytes;
+            self.window.pop_front();
+        }
+
+        let span = now.duration_since(self.start).min(SPEED_WINDOW);
+        let measured = (self.window_bytes as f64 / span.as_secs_f64().max(0.001)) as u64;
+        if measured >= self.required {
+            self.below_since = None;
+            return Ok(());
+        }
+        let below_since = *self.below_since.get_or_insert(now);
+        if now.duration_since(below_since) > self.timeout {
+            Err(measured)
+        } else {
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,109 @@
+use std::fmt;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::{Duration, Instant};
+
+// Durations of the phases of one upload or download, None for phases that were not measured
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PhaseTimings {
+    pub dns: Option<Duration>,
+    pub connect: Option<Duration>,
+    pub tls: Option<Duration>,
+    // Until the response headers arrived, for uploads this includes sending the request body
+    pub ttfb: Option<Duration>,
+    // Reading the response body, downloads only
+    pub transfer: Option<Duration>,
+}
+
+impl PhaseTimings {
+    // Adds the connection setup phases of a probe
+    pub fn with_setup(self, setup: PhaseTimings) -> Self {
+        Self {
+            dns: setup.dns,
+            connect: setup.connect,
+            tls: setup.tls,
+            ..self
+        }
+    }
+}
+
+impl fmt::Display for PhaseTimings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let phases = [
+            ("DNS", self.dns),
+            ("connect", self.connect),
+            ("TLS", self.tls),
+            ("TTFB", self.ttfb),
+            ("transfer", self.transfer),
+        ];
+        for (i, (name, duration)) in phases.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            match duration {
+                Some(duration) => write!(f, "{} {:.2?}", name, duration)?,
+                None => write!(f, "{} -", name)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+// Milliseconds with microsecond precision, more digits are only float noise
+pub fn millis(duration: Option<Duration>) -> Option<f64> {
+    duration.map(|duration| (duration.as_secs_f64() * 1e6).round() / 1e3)
+}
+
+// reqwest does not expose the phases of its connections, so connection setup is measured on a
+// separate connection to the server: a name lookup, a TCP connect and for https a TLS handshake
+// that does not check the certificate. Reused connections skip these phases, the probe shows what
+// setting up a new connection costs at that moment.
+pub fn probe_connection(server_url: &str, connect_timeout: Duration) -> PhaseTimings {
+    let mut timings = PhaseTimings::default();
+    let Ok(url) = reqwest::Url::parse(server_url) else {
+        return timings;
+    };
+    let (Some(host), Some(port)) = (url.host_str(), url.port_or_known_default()) else {
+        return timings;
+    };
+    // IPv6 literals are bracketed in URLs
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+
+    let start_time = Instant::now();
+    let address = match (host, port).to_socket_addrs() {
+        Ok(mut addresses) => addresses.next(),
+        Err(e) => {
+            tracing::debug!("Timing probe: unable to resolve {}: {}", host, e);
+            None
+        }
+    };
+    let Some(address) = address else {
+        return timings;
+    };
+    timings.dns = Some(start_time.elapsed());
+
+    let start_time = Instant::now();
+    let stream = match TcpStream::connect_timeout(&address, connect_timeout) {
+        Ok(stream) => stream,
+        Err(e) => {
+            tracing::debug!("Timing probe: unable to connect to {}: {}", address, e);
+            return timings;
+        }
+    };
+    timings.connect = Some(start_time.elapsed());
+
+    if url.scheme() == "https" {
+        let _ = stream.set_read_timeout(Some(connect_timeout));
+        let start_time = Instant::now();
+        let handshake = native_tls::TlsConnector::builder()
+            .danger_accept_invalid_certs(true)
+            .danger_accept_invalid_hostnames(true)
+            .build()
+            .map_err(|e| e.to_string())
+            .and_then(|connector| connector.connect(host, stream).map_err(|e| e.to_string()));
+        match handshake {
+            Ok(_) => timings.tls = Some(start_time.elapsed()),
+            Err(e) => tracing::debug!("Timing probe: TLS handshake with {} failed: {}", host, e),
+        }
+    }
+    timings
+}
@@ -0,0 +1,184 @@
+use crate::timing::PhaseTimings;
+use std::fmt::Write;
+use std::time::Duration;
+
+// Variables of a --write-out format, names as in curl where curl has them
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Variable {
+    Operation,
+    Filename,
+    Url,
+    HttpStatus,
+    Size,
+    Hash,
+    TimeTotal,
+    TimeDns,
+    TimeConnect,
+    TimeTls,
+    TimeTtfb,
+    TimeTransfer,
+}
+
+impl Variable {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "operation" => Some(Variable::Operation),
+            "filename" => Some(Variable::Filename),
+            "url" => Some(Variable::Url),
+            "http_status" | "http_code" => Some(Variable::HttpStatus),
+            "size" => Some(Variable::Size),
+            "hash" | "sha256" => Some(Variable::Hash),
+            "time_total" => Some(Variable::TimeTotal),
+            "time_dns" | "time_namelookup" => Some(Variable::TimeDns),
+            "time_connect" => Some(Variable::TimeConnect),
+            "time_tls" | "time_appconnect" => Some(Variable::TimeTls),
+            "time_ttfb" | "time_starttransfer" => Some(Variable::TimeTtfb),
+            "time_transfer" => Some(Variable::TimeTransfer),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Segment {
+    Text(String),
+    Variable(Variable),
+}
+
+// A parsed --write-out format, printed once per upload and download
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WriteOut {
+    segments: Vec<Segment>,
+}
+
+// What a --write-out line can show about one upload or download
+pub struct WriteOutValues<'a> {
+    pub operation: &'a str,
+    pub filename: &'a str,
+    pub url: &'a str,
+    // None when no response was received
+    pub status: Option<u16>,
+    pub size: Option<u64>,
+    pub hash: Option<&'a str>,
+    pub total: Duration,
+    pub phases: &'a PhaseTimings,
+}
+
+impl WriteOut {
+    // Parses %{variable}, %% and the escapes \n, \t and \\, unknown variables are an error
+    pub fn parse(format: &str) -> Result<Self, String> {
+        let mut segments = Vec::new();
+        let mut text = String::new();
+        let mut chars = format.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match (c, chars.peek()) {
+                ('\\', Some('n')) => text.push('\n'),
+                ('\\', Some('t')) => text.push('\t'),
+                ('\\', Some('\\')) => text.push('\\'),
+                ('%', Some('%')) => text.push('%'),
+                ('%', Some('{')) => {
+                    chars.next();
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => return Err(format!("unterminated variable %{{{}", name)),
+                        }
+                    }
+                    let variable = Variable::from_name(&name)
+                        .ok_or_else(|| format!("unknown variable %{{{}}}", name))?;
+                    if !text.is_empty() {
+                        segments.push(Segment::Text(std::mem::take(&mut text)));
+                    }
+                    segments.push(Segment::Variable(variable));
+                    continue;
+                }
+                _ => {
+                    text.push(c);
+                    continue;
+                }
+            }
+            // Second character of an escape
+            chars.next();
+        }
+        if !text.is_empty() {
+            segments.push(Segment::Text(text));
+        }
+        Ok(Self { segments })
+    }
+
+    pub fn uses(&self, variable: Variable) -> bool {
+        self.segments.contains(&Segment::Variable(variable))
+    }
+
+    // Unknown values are empty, except a missing status which is 000 as in curl
+    pub fn render(&self, values: &WriteOutValues) -> String {
+        let seconds = |duration: Option<Duration>| {
+            duration.map_or(String::new(), |duration| {
+                format!("{:.6}", duration.as_secs_f64())
+            })
+        };
+        let mut line = String::new();
+        for segment in &self.segments {
+            let variable = match segment {
+                Segment::Text(text) => {
+                    line.push_str(text);
+                    continue;
+                }
+                Segment::Variable(variable) => variable,
+            };
+            let _ = match variable {
+                Variable::Operation => write!(line, "{}", values.operation),
+                Variable::Filename => write!(line, "{}", values.filename),
+                Variable::Url => write!(line, "{}", values.url),
+                Variable::HttpStatus => match values.status {
+                    Some(status) => write!(line, "{}", status),
+                    None => write!(line, "000"),
+                },
+                Variable::Size => write!(
+                    line,
+                    "{}",
+                    values.size.map_or(String::new(), |size| size.to_string())
+                ),
+                Variable::Hash => write!(line, "{}", values.hash.unwrap_or_default()),
+                Variable::TimeTotal => write!(line, "{}", seconds(Some(values.total))),
+                Variable::TimeDns => write!(line, "{}", seconds(values.phases.dns)),
+                Variable::TimeConnect => write!(line, "{}", seconds(values.phases.connect)),
+                Variable::TimeTls => write!(line, "{}", seconds(values.phases.tls)),
+                Variable::TimeTtfb => write!(line, "{}", seconds(values.phases.ttfb)),
+                Variable::TimeTransfer => write!(line, "{}", seconds(values.phases.transfer)),
+            };
+        }
+        line
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_out_format() {
+        let write_out = WriteOut::parse(r"%{http_code} %{size} 100%% %{sha256}\n").unwrap();
+        let phases = PhaseTimings::default();
+        let mut values = WriteOutValues {
+            operation: "download",
+            filename: "a.bin",
+            url: "http://localhost/a.bin",
+            status: Some(200),
+            size: Some(42),
+            hash: Some("abc"),
+            total: Duration::from_millis(1500),
+            phases: &phases,
+        };
+        assert_eq!(write_out.render(&values), "200 42 100% abc\n");
+        values.status = None;
+        let write_out = WriteOut::parse("%{http_status} %{time_total} %{time_ttfb}").unwrap();
+        assert_eq!(write_out.render(&values), "000 1.500000 ");
+
+        assert!(WriteOut::parse("%{bogus}").unwrap_err().contains("bogus"));
+        assert!(WriteOut::parse("%{size").is_err());
+    }
+}